@@ -0,0 +1,78 @@
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::interval::{Interval, EMPTY as EMPTY_INTERVAL};
+
+#[derive(Clone, Copy)]
+pub struct Aabb { // Axis-aligned bounding box, one interval per axis
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval
+}
+
+impl Aabb {
+    pub fn from_points(a: Vec3, b: Vec3) -> Aabb { // Box with a and b as opposite corners
+        Aabb {
+            x: Interval {min: a.0.min(b.0), max: a.0.max(b.0)},
+            y: Interval {min: a.1.min(b.1), max: a.1.max(b.1)},
+            z: Interval {min: a.2.min(b.2), max: a.2.max(b.2)}
+        }.pad()
+    }
+
+    pub fn merge(a: Aabb, b: Aabb) -> Aabb { // Smallest box enclosing both
+        Aabb {
+            x: Interval::merge(a.x, b.x),
+            y: Interval::merge(a.y, b.y),
+            z: Interval::merge(a.z, b.z)
+        }
+    }
+
+    pub fn axis(self, n: usize) -> Interval {
+        match n {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z
+        }
+    }
+
+    pub fn centroid(self) -> Vec3 {
+        Vec3(
+            (self.x.min + self.x.max)/2.,
+            (self.y.min + self.y.max)/2.,
+            (self.z.min + self.z.max)/2.
+        )
+    }
+
+    pub fn surface_area(self) -> f64 {
+        let (dx, dy, dz): (f64, f64, f64) = (self.x.size(), self.y.size(), self.z.size());
+        if (dx < 0. || dy < 0. || dz < 0.) {
+            return 0.;                                                              // Empty box
+        }
+        2.*(dx*dy + dy*dz + dz*dx)
+    }
+
+    pub fn hit(self, r: Ray, inv_dir: Vec3, t_range: Interval) -> bool { // Slab test, inv_dir holds the reciprocals of r.dir
+        let mut t_min: f64 = t_range.min;
+        let mut t_max: f64 = t_range.max;
+        for (slab, ori, inv) in [(self.x, r.ori.0, inv_dir.0), (self.y, r.ori.1, inv_dir.1), (self.z, r.ori.2, inv_dir.2)] {
+            let t0: f64 = (slab.min - ori)*inv;
+            let t1: f64 = (slab.max - ori)*inv;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+            if (t_max < t_min) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn pad(self) -> Aabb { // Avoids zero-thickness boxes around axis-aligned flat primitives
+        let delta: f64 = 0.0001;
+        Aabb {
+            x: if (self.x.size() < delta) {self.x.expand(delta)} else {self.x},
+            y: if (self.y.size() < delta) {self.y.expand(delta)} else {self.y},
+            z: if (self.z.size() < delta) {self.z.expand(delta)} else {self.z}
+        }
+    }
+}
+
+pub const EMPTY: Aabb = Aabb {x: EMPTY_INTERVAL, y: EMPTY_INTERVAL, z: EMPTY_INTERVAL};
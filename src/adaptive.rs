@@ -0,0 +1,57 @@
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy)]
+pub struct Adaptive { // Stops sampling a pixel once its estimated error is low enough, the camera's samples being the cap
+    pub min_samples: u32, // Taken everywhere before the first check, the batches after it double in size
+    pub threshold: f64    // Largest accepted standard error of the mean, relative to the pixel's brightness
+}
+
+impl Default for Adaptive {
+    fn default() -> Adaptive {
+        Adaptive {min_samples: 16, threshold: 0.05}
+    }
+}
+
+impl Adaptive {
+    pub fn converged(&self, stats: &Stats) -> bool {
+        stats.relative_error() <= self.threshold
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Stats { // Running mean and variance of the luminance of a pixel's samples (Welford 1962)
+    pub n: u32,
+    pub mean: f64,
+    pub m2: f64    // Sum of squared differences from the mean
+}
+
+impl Stats {
+    pub fn add(&mut self, c: Vec3) {
+        let l: f64 = 0.2126*c.0 + 0.7152*c.1 + 0.0722*c.2;
+        self.n += 1;
+        let delta: f64 = l - self.mean;
+        self.mean += delta/self.n as f64;
+        self.m2 += delta*(l - self.mean);
+    }
+
+    pub fn variance(&self) -> f64 { // Unbiased sample variance
+        if (self.n < 2) {
+            return f64::INFINITY;
+        }
+        self.m2/(self.n - 1) as f64
+    }
+
+    pub fn relative_error(&self) -> f64 { // Standard error of the mean over the mean, floored so black pixels don't need infinite precision
+        (self.variance()/self.n as f64).sqrt()/(self.mean.max(0.) + 0.01)
+    }
+}
+
+pub fn heatmap(counts: &[u32], max: u32) -> Vec<Vec3> { // Samples per pixel from black through blue, red and yellow to white at max
+    const RAMP: [Vec3; 5] = [Vec3(0., 0., 0.), Vec3(0.1, 0.1, 0.8), Vec3(0.9, 0.1, 0.1), Vec3(1., 0.9, 0.1), Vec3(1., 1., 1.)];
+    counts.iter().map(|n| {
+        let t: f64 = (*n as f64/max.max(1) as f64).clamp(0., 1.)*(RAMP.len() - 1) as f64;
+        let k: usize = (t as usize).min(RAMP.len() - 2);
+        let f: f64 = t - k as f64;
+        (1. - f)*RAMP[k] + f*RAMP[k + 1]
+    }).collect()
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use crate::vec3::Vec3;
+use crate::color::{write_png, write_png_linear, Layer};
+use crate::random::hash;
+
+#[derive(Clone, Copy)]
+pub struct Aovs { // What the camera saw first along a sample, or the average over a pixel's samples
+    pub depth: f64,            // Camera-space depth, infinite on a miss
+    pub normal: Vec3,          // Shading normal, facing the incoming ray
+    pub albedo: Vec3,          // Reflectance of the first surface, or the background color on a miss
+    pub position: Vec3,        // World-space hit point
+    pub object: Option<usize>  // Index of the first surface within the world
+}
+
+impl Aovs {
+    pub fn miss() -> Aovs {
+        Aovs {
+            depth: f64::INFINITY,
+            normal: Vec3(0., 0., 0.),
+            albedo: Vec3(0., 0., 0.),
+            position: Vec3(0., 0., 0.),
+            object: None
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AovSum { // Running sums over a pixel's samples, so none of them need keeping
+    sum: Aovs,
+    samples: u32,
+    hits: u32
+}
+
+impl Default for AovSum {
+    fn default() -> AovSum {
+        AovSum {sum: Aovs {depth: 0., ..Aovs::miss()}, samples: 0, hits: 0}
+    }
+}
+
+impl AovSum {
+    pub fn add(&mut self, s: &Aovs) {
+        self.sum.albedo += s.albedo;
+        self.samples += 1;
+        if (s.object.is_some()) {
+            self.sum.depth += s.depth;
+            self.sum.normal += s.normal;
+            self.sum.position += s.position;
+            self.sum.object = self.sum.object.or(s.object);
+            self.hits += 1;
+        }
+    }
+
+    pub fn average(&self) -> Aovs { // Albedo over all samples, geometry over the ones that hit, object of the first one that hit
+        let mut px: Aovs = Aovs {object: self.sum.object, ..Aovs::miss()};
+        if (self.samples > 0) {
+            px.albedo = self.sum.albedo/self.samples as f64;
+        }
+        if (self.hits > 0) {
+            px.depth = self.sum.depth/self.hits as f64;
+            px.normal = if (self.sum.normal.near_zero()) {self.sum.normal} else {self.sum.normal.unit()};
+            px.position = self.sum.position/self.hits as f64;
+        }
+        px
+    }
+}
+
+pub fn pass_path(output_file: &str, pass: &str) -> String { // out/img.png -> out/img.depth.png
+    let path: &Path = Path::new(output_file);
+    let stem: &str = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let name: String = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}.{}", stem, pass, ext),
+        None => format!("{}.{}", stem, pass)
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+pub fn layers(aovs: &[Aovs]) -> Vec<Layer> { // Raw passes for float formats
+    vec![
+        Layer::gray("depth", "Z", aovs.iter().map(|a| a.depth as f32).collect()),
+        Layer::rgb("normal", &aovs.iter().map(|a| a.normal).collect::<Vec<Vec3>>()),
+        Layer::rgb("albedo", &aovs.iter().map(|a| a.albedo).collect::<Vec<Vec3>>()),
+        Layer::rgb("position", &aovs.iter().map(|a| a.position).collect::<Vec<Vec3>>()),
+        Layer::gray("object", "id", aovs.iter().map(|a| a.object.map_or(0., |id| (id + 1) as f32)).collect()) // Surface index plus one, 0 on a miss
+    ]
+}
+
+pub fn write_aovs(output_file: &str, w: u32, h: u32, aovs: &[Aovs]) { // One image per pass next to the beauty image, remapped to be viewable
+    let max_depth: f64 = aovs.iter().map(|a| a.depth).filter(|d| d.is_finite()).fold(0., f64::max);
+    let depth: Vec<Vec3> = aovs.iter().map(|a| {
+        let d: f64 = if (a.depth.is_finite() && max_depth > 0.) {1. - 0.9*a.depth/max_depth} else {0.}; // Near is bright, the farthest hit is dim and misses are black
+        Vec3(d, d, d)
+    }).collect();
+    write_png_linear(pass_path(output_file, "depth"), w, h, &depth);
+
+    let normal: Vec<Vec3> = aovs.iter().map(|a| if (a.object.is_some()) {0.5*(1. + a.normal)} else {Vec3(0., 0., 0.)}).collect();
+    write_png_linear(pass_path(output_file, "normal"), w, h, &normal);
+
+    let albedo: Vec<Vec3> = aovs.iter().map(|a| a.albedo).collect();
+    write_png(pass_path(output_file, "albedo"), w, h, &albedo);
+
+    let (mut lo, mut hi): (Vec3, Vec3) = (Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vec3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY));
+    for a in aovs.iter().filter(|a| a.object.is_some()) {
+        lo = Vec3(lo.0.min(a.position.0), lo.1.min(a.position.1), lo.2.min(a.position.2));
+        hi = Vec3(hi.0.max(a.position.0), hi.1.max(a.position.1), hi.2.max(a.position.2));
+    }
+    let extent: Vec3 = hi - lo;
+    let position: Vec<Vec3> = aovs.iter().map(|a| match a.object {                // Scene bounds seen by the camera mapped to the unit cube
+        Some(_) => Vec3(
+            if (extent.0 > 0.) {(a.position.0 - lo.0)/extent.0} else {0.},
+            if (extent.1 > 0.) {(a.position.1 - lo.1)/extent.1} else {0.},
+            if (extent.2 > 0.) {(a.position.2 - lo.2)/extent.2} else {0.}
+        ),
+        None => Vec3(0., 0., 0.)
+    }).collect();
+    write_png_linear(pass_path(output_file, "position"), w, h, &position);
+
+    let object: Vec<Vec3> = aovs.iter().map(|a| match a.object {                  // A random but stable color per surface
+        Some(id) => {
+            let bits: u64 = hash(id as u64, 0);
+            Vec3((bits & 0xff) as f64/255., ((bits >> 8) & 0xff) as f64/255., ((bits >> 16) & 0xff) as f64/255.)
+        },
+        None => Vec3(0., 0., 0.)
+    }).collect();
+    write_png_linear(pass_path(output_file, "object"), w, h, &object);
+}
@@ -0,0 +1,218 @@
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::aabb::{Aabb, EMPTY};
+use crate::interval::{Interval, EMPTY as EMPTY_INTERVAL};
+use crate::surface::{HitRecord, Hittable};
+
+const BINS: usize = 12;           // Candidate split planes per axis are the boundaries between these bins
+const MAX_LEAF: usize = 4;        // Larger leaves are split even when the SAH says it isn't worth it
+const TRAVERSAL_COST: f64 = 0.5;  // Cost of visiting an interior node relative to one intersection test
+const MAX_SAH_DEPTH: usize = 32;  // Deeper nodes split at the median, so the tree stays within the traversal stack
+const STACK_SIZE: usize = 64;     // MAX_SAH_DEPTH plus the median splits of up to 2^32 surfaces
+
+#[derive(Clone, Copy)]
+struct Node {
+    bbox: Aabb,
+    offset: u32, // Leaf: first entry in indices. Interior: index of the second child, the first one follows the node
+    count: u32,  // Number of surfaces in a leaf, 0 for interior nodes
+    axis: u8     // Split axis of interior nodes
+}
+
+pub struct Bvh { // Bounding volume hierarchy flattened in depth-first order
+    nodes: Vec<Node>,
+    indices: Vec<usize> // Surface indices, each leaf references a contiguous run
+}
+
+impl Bvh {
+    pub fn new(boxes: &[Aabb]) -> Bvh {
+        let mut indices: Vec<usize> = (0..boxes.len()).collect();
+        let centroids: Vec<Vec3> = boxes.iter().map(|b| b.centroid()).collect();
+        let mut nodes: Vec<Node> = Vec::with_capacity(2*boxes.len());
+        if (!boxes.is_empty()) {
+            Self::build(boxes, &centroids, &mut indices, 0, boxes.len(), 0, &mut nodes);
+        }
+        Bvh {nodes, indices}
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or(EMPTY, |n| n.bbox)
+    }
+
+    fn build(boxes: &[Aabb], centroids: &[Vec3], indices: &mut [usize], start: usize, end: usize, depth: usize, nodes: &mut Vec<Node>) -> usize {
+        let node_idx: usize = nodes.len();
+        let bbox: Aabb = indices[start..end].iter().fold(EMPTY, |acc, &i| Aabb::merge(acc, boxes[i]));
+        let count: usize = end - start;
+        nodes.push(Node {bbox, offset: start as u32, count: count as u32, axis: 0});
+        if (count == 1) {
+            return node_idx;
+        }
+
+        let mut c_bounds: [Interval; 3] = [EMPTY_INTERVAL; 3];                      // Bounds of the centroids, which is what gets binned
+        for &i in indices[start..end].iter() {
+            let c: Vec3 = centroids[i];
+            for (axis, x) in [c.0, c.1, c.2].into_iter().enumerate() {
+                c_bounds[axis] = Interval::merge(c_bounds[axis], Interval {min: x, max: x});
+            }
+        }
+
+        let bin_of = |c: Vec3, axis: usize| -> usize {
+            let x: f64 = [c.0, c.1, c.2][axis];
+            let rel: f64 = (x - c_bounds[axis].min)/c_bounds[axis].size();
+            ((rel*BINS as f64) as usize).min(BINS - 1)
+        };
+
+        let mut best: Option<(usize, usize, f64)> = None;                           // Axis, first bin of the right side, cost
+        for (axis, bounds) in c_bounds.iter().enumerate() {
+            if (bounds.size() <= 0. || depth >= MAX_SAH_DEPTH) {                    // Forced SAH splits can peel off one surface per level, deeper down halving bounds the depth
+                continue;
+            }
+            let mut bin_boxes: [Aabb; BINS] = [EMPTY; BINS];
+            let mut bin_counts: [usize; BINS] = [0; BINS];
+            for &i in indices[start..end].iter() {
+                let b: usize = bin_of(centroids[i], axis);
+                bin_boxes[b] = Aabb::merge(bin_boxes[b], boxes[i]);
+                bin_counts[b] += 1;
+            }
+
+            let mut right_area: [f64; BINS] = [0.; BINS];                           // right_area[k] covers bins k..BINS
+            let mut right_count: [usize; BINS] = [0; BINS];
+            let mut acc: Aabb = EMPTY;
+            let mut n: usize = 0;
+            for k in (1..BINS).rev() {
+                acc = Aabb::merge(acc, bin_boxes[k]);
+                n += bin_counts[k];
+                right_area[k] = acc.surface_area();
+                right_count[k] = n;
+            }
+
+            let mut acc: Aabb = EMPTY;
+            let mut n: usize = 0;
+            for k in 1..BINS {
+                acc = Aabb::merge(acc, bin_boxes[k - 1]);
+                n += bin_counts[k - 1];
+                if (n == 0 || right_count[k] == 0) {
+                    continue;
+                }
+                let cost: f64 = TRAVERSAL_COST + (acc.surface_area()*n as f64 + right_area[k]*right_count[k] as f64)/bbox.surface_area();
+                if (best.is_none_or(|(_, _, best_cost)| cost < best_cost)) {
+                    best = Some((axis, k, cost));
+                }
+            }
+        }
+
+        let mid: usize = match best {
+            Some((axis, split, cost)) if (cost < count as f64 || count > MAX_LEAF) => {
+                let mut mid: usize = start;
+                for k in start..end {
+                    if (bin_of(centroids[indices[k]], axis) < split) {
+                        indices.swap(k, mid);
+                        mid += 1;
+                    }
+                }
+                nodes[node_idx].axis = axis as u8;
+                mid
+            },
+            None if (count > MAX_LEAF) => {                                         // Too deep, or all centroids coincide, split at the median of the widest axis
+                let axis: usize = (0..3).max_by(|&a, &b| c_bounds[a].size().total_cmp(&c_bounds[b].size())).unwrap_or(0);
+                let coord = |i: usize| -> f64 {[centroids[i].0, centroids[i].1, centroids[i].2][axis]};
+                indices[start..end].select_nth_unstable_by(count/2, |&a, &b| coord(a).total_cmp(&coord(b)));
+                nodes[node_idx].axis = axis as u8;
+                start + count/2
+            },
+            _ => return node_idx                                                    // Cheaper to keep as a leaf
+        };
+
+        nodes[node_idx].count = 0;
+        Self::build(boxes, centroids, indices, start, mid, depth + 1, nodes);
+        let right: usize = Self::build(boxes, centroids, indices, mid, end, depth + 1, nodes);
+        nodes[node_idx].offset = right as u32;
+        node_idx
+    }
+
+    pub fn hit<'a, T: Hittable>(&self, items: &'a [T], r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool { // Nearest hit among items, setting rec.id to its index
+        if (self.nodes.is_empty()) {
+            return false;
+        }
+        let inv_dir: Vec3 = Vec3(1./r.dir.0, 1./r.dir.1, 1./r.dir.2);
+        let dir_neg: [bool; 3] = [inv_dir.0 < 0., inv_dir.1 < 0., inv_dir.2 < 0.];
+        let mut stack: [usize; STACK_SIZE] = [0; STACK_SIZE];
+        let mut sp: usize = 0;
+        let mut node_idx: usize = 0;
+        let mut temp_rec: HitRecord<'a> = HitRecord::new_empty();
+        let mut world_hit: bool = false;
+        let mut nearest: f64 = t_range.max;
+        loop {
+            let node: &Node = &self.nodes[node_idx];
+            if (node.bbox.hit(r, inv_dir, Interval {min: t_range.min, max: nearest})) {
+                if (node.count > 0) {
+                    let first: usize = node.offset as usize;
+                    for &i in self.indices[first..first + node.count as usize].iter() {
+                        if (items[i].hit(r, Interval {min: t_range.min, max: nearest}, &mut temp_rec)) {
+                            temp_rec.id = i;
+                            nearest = temp_rec.t;
+                            world_hit = true;
+                            *rec = temp_rec;
+                        }
+                    }
+                } else {                                                            // Visit the child nearer to the ray origin first
+                    let (near, far): (usize, usize) = if (dir_neg[node.axis as usize]) {
+                        (node.offset as usize, node_idx + 1)
+                    } else {
+                        (node_idx + 1, node.offset as usize)
+                    };
+                    stack[sp] = far;
+                    sp += 1;
+                    node_idx = near;
+                    continue;
+                }
+            }
+            if (sp == 0) {
+                break;
+            }
+            sp -= 1;
+            node_idx = stack[sp];
+        }
+        world_hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use crate::materials::{Materials, Lambertian};
+    use crate::texture::Textures;
+    use crate::random::{random_in, seed_rng};
+
+    fn spheres() -> Vec<Sphere> { // Clustered and overlapping, plus a chain along x that forces the depth limit
+        let mat: Materials = Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.5, 0.5, 0.5))});
+        let mut items: Vec<Sphere> = (0..300).map(|_| Sphere {center: Vec3::random_in(-5., 5.), center1: None, radius: random_in(0.05, 1.), mat: mat.clone()}).collect();
+        items.extend((0..80).map(|k| Sphere {center: Vec3(1.5f64.powi(k), 0., 0.), center1: None, radius: 0.1, mat: mat.clone()}));
+        items
+    }
+
+    #[test]
+    fn matches_linear_scan() {
+        seed_rng(11);
+        let items: Vec<Sphere> = spheres();
+        let bvh: Bvh = Bvh::new(&items.iter().map(|s| s.bounding_box()).collect::<Vec<Aabb>>());
+        let t_range: Interval = Interval {min: 0.001, max: f64::INFINITY};
+        for _ in 0..2000 {
+            let r: Ray = Ray {ori: Vec3::random_in(-8., 8.), dir: Vec3::random_unit(), time: 0.};
+            let mut rec: HitRecord = HitRecord::new_empty();
+            let hit: bool = bvh.hit(&items, r, t_range, &mut rec);
+
+            let mut nearest: Option<(usize, f64)> = None;
+            for (i, item) in items.iter().enumerate() {
+                let mut temp: HitRecord = HitRecord::new_empty();
+                if (item.hit(r, Interval {min: t_range.min, max: nearest.map_or(t_range.max, |(_, t)| t)}, &mut temp)) {
+                    nearest = Some((i, temp.t));
+                }
+            }
+            assert!(hit == nearest.is_some());
+            if let Some((i, t)) = nearest {
+                assert!(rec.id == i && rec.t == t);
+            }
+        }
+    }
+}
@@ -1,13 +1,45 @@
+use std::fmt;
 use std::fs::File;
 use std::io::{stdout, BufWriter, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::f64::consts::PI;
 
 use crate::vec3::Vec3;
 use crate::ray::Ray;
-use crate::color::write_png;
+use crate::color::{write_png, write_png_linear, write_exr, write_hdr, write_pfm, Format, Layer};
+use crate::aov::{self, Aovs, AovSum, write_aovs, pass_path};
+use crate::denoise::Denoiser;
+use crate::tonemap::ToneMap;
 use crate::surface::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::world::World;
 use crate::materials::{Materials, Scatter};
+use crate::integrator::Integrator;
+use crate::random::{hash, DEFAULT_SEED};
+use crate::sampler::{Sampler, SamplerKind};
+use crate::film::{Film, PixelFilter, MIN_RADIUS};
+use crate::adaptive::{self, Adaptive, Stats};
+use crate::checkpoint::Checkpoint;
+
+const TILE_SIZE: u32 = 16; // Side of the square pixel blocks handed out to worker threads
+const PASS_SAMPLES: u32 = 16; // Samples per pixel in each pass of a checkpointed render, unless set
+
+struct Tile { // What a worker hands back for one block of pixels in one pass
+    index: u32,              // Row-major position among the image's tiles
+    film: Film,              // Samples splatted from the block, reaching past it by the filter radius
+    aovs: Vec<Option<Aovs>>, // Row-major averaged passes of the pixels sampled for the first time
+    stats: Vec<Stats>,       // Row-major running statistics, including this pass
+    done: Vec<bool>          // Row-major, whether adaptive sampling stopped the pixel
+}
+
+#[derive(Clone, Copy)]
+pub enum Projection {
+    Perspective {vertical_fov: f64}, // Pinhole or thin lens, field of view in degrees
+    Orthographic {view_width: f64},  // Parallel rays, view_width world units across the image
+    Equirectangular,                 // Full 360 by 180 degree panorama, meant for 2:1 images
+    Fisheye {fov: f64}               // Equidistant circular fisheye, fov degrees across the shorter image side
+}
 
 pub struct Camera {
     output_file: String,
@@ -16,152 +48,792 @@ pub struct Camera {
     up_dir: Vec3,    // Camera's relative up direction
     d_angle: f64,    // Variation angle of rays per pixels
     focus_len: f64,  // Distance from camera of perfect focus plane
-    max_d: u8,       // Max depth (n° of jumps)
-    vfov: f64,       // Vertical FOV
+    projection: Projection,
     ar: f64,         // Aspect ratio
     w: u32,          // Screen image_width
     h: u32,          // Screen height
     vh: f64,         // Viewport image_width
     vw: f64,         // Viewport height
+    i: Vec3,         // Camera frame basis vector u, pointing right
+    j: Vec3,         // Camera frame basis vector v, pointing up
+    k: Vec3,         // Camera frame basis vector w, pointing backwards
     vu: Vec3,        // Vu
     vv: Vec3,        // Vv
     du: Vec3,        // delta_u between pixels
     dv: Vec3,        // delta_v between pixels
     center: Vec3,    // Camera center
     v_corner: Vec3,  // Viewport upper-left corner location
-    aa_sqrt: u8,     // sqrt(Samples per pixel) for antialiasing
-    aa: u8,          // Samples per pixel for antialiasing
-    dus: Vec3,       // delta_u between samples
-    dvs: Vec3,       // delta_v between samples
-    s_corner: Vec3,  // Position of the first top-left sample
+    spp: u32,        // Samples per pixel
+    sampler: SamplerKind, // Generator of the sample values for pixel positions, lens, time and bounces
+    filter: PixelFilter, // Reconstruction filter spreading each sample over the pixels around it
+    filter_radius: f64, // In pixels
+    adaptive: Option<Adaptive>, // Per-pixel stopping rule, every pixel takes spp samples if None
+    heatmap: bool,   // Whether render also writes the number of samples taken per pixel
+    pass_samples: Option<u32>, // Samples per pixel of each progressive pass, a single pass if None
+    checkpoint: Option<String>, // Written after every pass, if any
+    resume: Option<Checkpoint>, // Earlier progress to carry on from
     dudd: Vec3,      // Defocus disk horizontal radius
-    dvdd: Vec3       // Defocus disk vertical radius
+    dvdd: Vec3,      // Defocus disk vertical radius
+    shutter: Interval, // Times between which rays are cast
+    aovs: bool,      // Whether render also writes the auxiliary passes
+    denoiser: Option<Denoiser>, // Filter for an extra denoised copy of the output, if any
+    tone_map: ToneMap,
+    exposure: f64,   // Stops applied before tone mapping
+    format: Format,
+    threads: usize,  // Number of worker threads used by render
+    seed: u64,       // Base of the per-pixel sample streams
+    fingerprint: u64, // Of the scene and the settings a checkpoint must agree on
+    integrator: Integrator
 }
 
-impl Camera {
-    pub fn new(
-        output_file: String,
-        look_at: Vec3,
-        look_from: Vec3,
-        up_dir: Vec3,
-        defocus_angle: f64,
-        focus_distance: f64,
-        aspect_ratio: f64, 
-        image_width: u32, 
-        max_depth: u8, 
-        vertical_fov: f64,
-        aa_factor: u8
-    ) -> Camera {
+#[derive(Clone)]
+pub struct CameraBuilder { // Named settings for a Camera, checked all at once by build
+    look_from: Vec3,
+    look_at: Vec3,
+    up_dir: Vec3,
+    defocus_angle: f64,          // Degrees, 0 for a pinhole
+    focus_distance: Option<f64>, // Defaults to the distance between look_from and look_at
+    aspect_ratio: f64,
+    image_width: u32,
+    max_depth: Option<u32>,
+    rr_depth: u32,
+    projection: Projection,
+    samples: u32,
+    sampler: SamplerKind,
+    filter: PixelFilter,
+    filter_radius: Option<f64>,  // Defaults to the filter's own
+    adaptive: Option<Adaptive>,
+    heatmap: bool,
+    pass_samples: Option<u32>,   // Defaults to PASS_SAMPLES with a checkpoint, to a single pass otherwise
+    checkpoint: Option<String>,
+    resume: Option<Checkpoint>,
+    shutter: (f64, f64),
+    threads: Option<usize>,      // Defaults to the available parallelism
+    seed: u64,
+    scene_fingerprint: u64,      // Hash of the scene contents, 0 if unknown
+    aovs: bool,
+    denoiser: Option<Denoiser>,
+    tone_map: ToneMap,
+    exposure: f64,
+    format: Option<Format>,      // Defaults to the one matching the output file extension
+    output_file: Option<String>
+}
+
+#[derive(Debug)]
+pub enum CameraError {
+    ZeroWidth,
+    ZeroHeight {width: u32, aspect_ratio: f64},
+    AspectRatio(f64),
+    NotFinite(&'static str),
+    LookAtLookFrom,  // look_from == look_at leaves no viewing direction
+    UpParallel,      // up_dir along the viewing direction leaves no horizontal axis
+    FieldOfView(f64),
+    ViewWidth(f64),
+    FocusDistance(f64),
+    DefocusAngle(f64),
+    ZeroSamples,
+    FilterRadius(f64),
+    MinSamples {min: u32, max: u32},
+    Threshold(f64),
+    ZeroPassSamples,
+    Resume(String),  // Checkpoint taken with different settings
+    ZeroThreads,
+    Shutter {open: f64, close: f64},
+    Exposure(f64),
+    WhitePoint(f64),
+    NoOutputFile
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CameraError::ZeroWidth => write!(f, "image width must be positive"),
+            CameraError::ZeroHeight {width, aspect_ratio} => write!(f, "a {} px wide image with aspect ratio {} would have no rows", width, aspect_ratio),
+            CameraError::AspectRatio(ar) => write!(f, "aspect ratio must be positive, got {}", ar),
+            CameraError::NotFinite(name) => write!(f, "{} must be finite", name),
+            CameraError::LookAtLookFrom => write!(f, "look_at must differ from look_from"),
+            CameraError::UpParallel => write!(f, "up direction must not be parallel to the viewing direction"),
+            CameraError::FieldOfView(fov) => write!(f, "field of view {} is out of range", fov),
+            CameraError::ViewWidth(vw) => write!(f, "orthographic view width must be positive, got {}", vw),
+            CameraError::FocusDistance(d) => write!(f, "focus distance must be positive, got {}", d),
+            CameraError::DefocusAngle(a) => write!(f, "defocus angle must be between 0 and 180 degrees, got {}", a),
+            CameraError::ZeroSamples => write!(f, "samples per pixel must be positive"),
+            CameraError::FilterRadius(r) => write!(f, "filter radius must be at least {} pixels, got {}", MIN_RADIUS, r),
+            CameraError::MinSamples {min, max} => write!(f, "minimum samples per pixel must be between 1 and the samples per pixel {}, got {}", max, min),
+            CameraError::Threshold(t) => write!(f, "adaptive sampling threshold must be positive, got {}", t),
+            CameraError::ZeroPassSamples => write!(f, "samples per pass must be positive"),
+            CameraError::Resume(msg) => write!(f, "can't resume from the checkpoint: {}", msg),
+            CameraError::ZeroThreads => write!(f, "thread count must be positive"),
+            CameraError::Shutter {open, close} => write!(f, "shutter closes at {} before it opens at {}", close, open),
+            CameraError::Exposure(e) => write!(f, "exposure must be finite, got {}", e),
+            CameraError::WhitePoint(l) => write!(f, "white point must be positive, got {}", l),
+            CameraError::NoOutputFile => write!(f, "no output file")
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
+
+impl Default for CameraBuilder {
+    fn default() -> CameraBuilder {
+        CameraBuilder {
+            look_from: Vec3(0., 0., 0.),
+            look_at: Vec3(0., 0., -1.),
+            up_dir: Vec3(0., 1., 0.),
+            defocus_angle: 0.,
+            focus_distance: None,
+            aspect_ratio: 16./9.,
+            image_width: 400,
+            max_depth: None,
+            rr_depth: 5,
+            projection: Projection::Perspective {vertical_fov: 90.},
+            samples: 16,
+            sampler: SamplerKind::Sobol,
+            filter: PixelFilter::Box,
+            filter_radius: None,
+            adaptive: None,
+            heatmap: false,
+            pass_samples: None,
+            checkpoint: None,
+            resume: None,
+            shutter: (0., 1.),
+            threads: None,
+            seed: DEFAULT_SEED,
+            scene_fingerprint: 0,
+            aovs: false,
+            denoiser: None,
+            tone_map: ToneMap::Clamp,
+            exposure: 0.,
+            format: None,
+            output_file: None
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn new() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
+    pub fn look_from(mut self, look_from: Vec3) -> CameraBuilder {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(mut self, look_at: Vec3) -> CameraBuilder {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn up(mut self, up_dir: Vec3) -> CameraBuilder {
+        self.up_dir = up_dir;
+        self
+    }
+
+    pub fn defocus_angle(mut self, degrees: f64) -> CameraBuilder {
+        self.defocus_angle = degrees;
+        self
+    }
+
+    pub fn focus_distance(mut self, distance: f64) -> CameraBuilder {
+        self.focus_distance = Some(distance);
+        self
+    }
+
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> CameraBuilder {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn image_width(mut self, width: u32) -> CameraBuilder {
+        self.image_width = width;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: Option<u32>) -> CameraBuilder { // None leaves path length to Russian roulette
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn rr_depth(mut self, rr_depth: u32) -> CameraBuilder {
+        self.rr_depth = rr_depth;
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> CameraBuilder {
+        self.projection = projection;
+        self
+    }
+
+    pub fn samples(mut self, samples: u32) -> CameraBuilder {
+        self.samples = samples;
+        self
+    }
+
+    pub fn sampler(mut self, sampler: SamplerKind) -> CameraBuilder {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn filter(mut self, filter: PixelFilter) -> CameraBuilder {
+        self.filter = filter;
+        self
+    }
+
+    pub fn filter_radius(mut self, radius: f64) -> CameraBuilder {
+        self.filter_radius = Some(radius);
+        self
+    }
+
+    pub fn adaptive(mut self, adaptive: Adaptive) -> CameraBuilder { // Samples become the most any pixel gets
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn heatmap(mut self, heatmap: bool) -> CameraBuilder { // Also write the samples taken per pixel, as out.samples.png
+        self.heatmap = heatmap;
+        self
+    }
+
+    pub fn progressive(mut self, pass_samples: u32) -> CameraBuilder { // Render in passes of pass_samples samples per pixel, rewriting the output after each
+        self.pass_samples = Some(pass_samples);
+        self
+    }
+
+    pub fn checkpoint(mut self, path: String) -> CameraBuilder { // Save progress to path after every pass, rendering progressively
+        self.checkpoint = Some(path);
+        self
+    }
+
+    pub fn resume(mut self, checkpoint: Checkpoint) -> CameraBuilder { // Carry on from checkpoint up to the set samples, which must come from the same scene and settings
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    pub fn shutter(mut self, open: f64, close: f64) -> CameraBuilder {
+        self.shutter = (open, close);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> CameraBuilder {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> CameraBuilder {
+        self.seed = seed;
+        self
+    }
+
+    pub fn scene_fingerprint(mut self, fingerprint: u64) -> CameraBuilder { // Identifies the scene, so a checkpoint of another one is refused
+        self.scene_fingerprint = fingerprint;
+        self
+    }
+
+    pub fn aovs(mut self, aovs: bool) -> CameraBuilder { // Also write depth, normal, albedo, position and object images next to the output
+        self.aovs = aovs;
+        self
+    }
+
+    pub fn denoiser(mut self, denoiser: Denoiser) -> CameraBuilder { // Also write a denoised copy of the output, as out.denoised.png
+        self.denoiser = Some(denoiser);
+        self
+    }
+
+    pub fn tone_map(mut self, tone_map: ToneMap) -> CameraBuilder {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn exposure(mut self, stops: f64) -> CameraBuilder {
+        self.exposure = stops;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> CameraBuilder {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn output_file(mut self, output_file: String) -> CameraBuilder {
+        self.output_file = Some(output_file);
+        self
+    }
+
+    fn validate(&self) -> Result<(), CameraError> {
+        if (self.image_width == 0) {
+            return Err(CameraError::ZeroWidth);
+        }
+        if (!(self.aspect_ratio.is_finite() && self.aspect_ratio > 0.)) {
+            return Err(CameraError::AspectRatio(self.aspect_ratio));
+        }
+        if ((self.image_width as f64/self.aspect_ratio) as u32 == 0) {
+            return Err(CameraError::ZeroHeight {width: self.image_width, aspect_ratio: self.aspect_ratio});
+        }
+        for (name, v) in [("look_from", self.look_from), ("look_at", self.look_at), ("up_dir", self.up_dir)] {
+            if (!(v.0.is_finite() && v.1.is_finite() && v.2.is_finite())) {
+                return Err(CameraError::NotFinite(name));
+            }
+        }
+        let view: Vec3 = self.look_from - self.look_at;
+        if (view.len() <= 1e-12*self.look_from.len().max(self.look_at.len())) {     // Relative, so it holds at any scene scale
+            return Err(CameraError::LookAtLookFrom);
+        }
+        if (self.up_dir.x(view).len() <= 1e-12*self.up_dir.len()*view.len()) {      // Sine of the angle between them, also rejects a zero up_dir
+            return Err(CameraError::UpParallel);
+        }
+        match self.projection {
+            Projection::Perspective {vertical_fov} if (!(vertical_fov > 0. && vertical_fov < 180.)) => return Err(CameraError::FieldOfView(vertical_fov)),
+            Projection::Fisheye {fov} if (!(fov > 0. && fov <= 360.)) => return Err(CameraError::FieldOfView(fov)),
+            Projection::Orthographic {view_width} if (!(view_width > 0. && view_width.is_finite())) => return Err(CameraError::ViewWidth(view_width)),
+            _ => ()
+        }
+        if let Some(d) = self.focus_distance {
+            if (!(d > 0. && d.is_finite())) {
+                return Err(CameraError::FocusDistance(d));
+            }
+        }
+        if (!(0. ..180.).contains(&self.defocus_angle)) {
+            return Err(CameraError::DefocusAngle(self.defocus_angle));
+        }
+        if (self.samples == 0) {
+            return Err(CameraError::ZeroSamples);
+        }
+        if let Some(r) = self.filter_radius {
+            if (!(r >= MIN_RADIUS && r.is_finite())) {
+                return Err(CameraError::FilterRadius(r));
+            }
+        }
+        if let Some(a) = self.adaptive {
+            if (a.min_samples == 0 || a.min_samples > self.samples) {
+                return Err(CameraError::MinSamples {min: a.min_samples, max: self.samples});
+            }
+            if (!(a.threshold > 0. && a.threshold.is_finite())) {
+                return Err(CameraError::Threshold(a.threshold));
+            }
+        }
+        if (self.pass_samples == Some(0)) {
+            return Err(CameraError::ZeroPassSamples);
+        }
+        if let Some(cp) = &self.resume {                                            // Anything the accumulated sums depend on must match
+            let h: u32 = (self.image_width as f64/self.aspect_ratio) as u32;
+            let radius: f64 = self.filter_radius.unwrap_or(self.filter.default_radius());
+            if (cp.w != self.image_width || cp.h != h) {
+                return Err(CameraError::Resume(format!("it is {}x{} but the image is {}x{}", cp.w, cp.h, self.image_width, h)));
+            }
+            if (cp.seed != self.seed) {
+                return Err(CameraError::Resume(format!("it was rendered with seed {} but the seed is {}", cp.seed, self.seed)));
+            }
+            if (cp.sampler != self.sampler) {
+                return Err(CameraError::Resume(String::from("it was rendered with a different sampler")));
+            }
+            if (cp.filter != self.filter || cp.filter_radius != radius) {
+                return Err(CameraError::Resume(String::from("it was rendered with a different filter or filter radius")));
+            }
+            if (cp.fingerprint != self.fingerprint()) {
+                return Err(CameraError::Resume(String::from("the scene, camera or integrator settings changed since it was written")));
+            }
+            if (cp.sampler == SamplerKind::Stratified && cp.spp != self.samples) { // The strata are laid out over the total, a new one would not complement those already taken
+                return Err(CameraError::Resume(format!("its stratified samples are laid out for {} samples per pixel, not {}", cp.spp, self.samples)));
+            }
+        }
+        if (self.threads == Some(0)) {
+            return Err(CameraError::ZeroThreads);
+        }
+        if (!(self.shutter.0.is_finite() && self.shutter.1.is_finite())) {
+            return Err(CameraError::NotFinite("shutter"));
+        }
+        if (self.shutter.0 > self.shutter.1) {
+            return Err(CameraError::Shutter {open: self.shutter.0, close: self.shutter.1});
+        }
+        if (!self.exposure.is_finite()) {
+            return Err(CameraError::Exposure(self.exposure));
+        }
+        if let ToneMap::ExtendedReinhard {white} = self.tone_map {
+            if (!(white > 0. && white.is_finite())) {
+                return Err(CameraError::WhitePoint(white));
+            }
+        }
+        if (self.output_file.is_none()) {
+            return Err(CameraError::NoOutputFile);
+        }
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> u64 { // Hash of the scene and of every setting besides those checked on their own that changes what a sample sees
+        let (kind, param): (u64, f64) = match self.projection {
+            Projection::Perspective {vertical_fov} => (0, vertical_fov),
+            Projection::Orthographic {view_width} => (1, view_width),
+            Projection::Equirectangular => (2, 0.),
+            Projection::Fisheye {fov} => (3, fov)
+        };
+        let focus_distance: f64 = self.focus_distance.unwrap_or((self.look_from - self.look_at).len());
+        let settings: [f64; 16] = [
+            self.look_from.0, self.look_from.1, self.look_from.2,
+            self.look_at.0, self.look_at.1, self.look_at.2,
+            self.up_dir.0, self.up_dir.1, self.up_dir.2,
+            self.defocus_angle, focus_distance, param,
+            self.max_depth.map_or(-1., |d| d as f64), self.rr_depth as f64,
+            self.shutter.0, self.shutter.1
+        ];
+        settings.iter().fold(hash(self.scene_fingerprint, kind), |h, x| hash(h, x.to_bits()))
+    }
+
+    pub fn build(self) -> Result<Camera, CameraError> {
+        self.validate()?;
+        let fingerprint: u64 = self.fingerprint();
+        let (look_from, look_at, up_dir): (Vec3, Vec3, Vec3) = (self.look_from, self.look_at, self.up_dir);
+        let (image_width, aspect_ratio, projection): (u32, f64, Projection) = (self.image_width, self.aspect_ratio, self.projection);
+        let focus_distance: f64 = self.focus_distance.unwrap_or((look_from - look_at).len());
+        let defocus_angle: f64 = self.defocus_angle;
+        let format: Format = self.format.unwrap_or_else(|| Format::from_path(self.output_file.as_deref().unwrap_or("")));
+
         let k: Vec3 = (look_from - look_at).unit(); // Camera coordinate frame unit basis vector w
         let i: Vec3 = (up_dir.x(k)).unit();         // Camera coordinate frame unit basis vector u
         let j: Vec3 = k.x(i);                       // Camera coordinate frame unit basis vector v
 
         let h: u32 = (image_width as f64/aspect_ratio) as u32;
-        let theta: f64 = vertical_fov.to_radians();
-        let vfov_h_ratio: f64 = (theta/2.).tan();
-        let vh: f64 = 2.*vfov_h_ratio*focus_distance;
-        let vw: f64 = vh*(image_width as f64/h as f64);        
+        let vh: f64 = match projection {                                            // Only perspective and orthographic rays go through the viewport
+            Projection::Orthographic {view_width} => view_width*(h as f64/image_width as f64),
+            Projection::Perspective {vertical_fov} => 2.*(vertical_fov.to_radians()/2.).tan()*focus_distance,
+            _ => 2.*focus_distance
+        };
+        let vw: f64 = vh*(image_width as f64/h as f64);
         let vu: Vec3 = vw*i;
         let vv: Vec3 = -vh*j;
         let du: Vec3 = vu/(image_width as f64);
         let dv: Vec3 = vv/(h as f64);
         let center: Vec3 = look_from;
         let v_corner: Vec3 = center - (focus_distance*k) - vu/2. - vv/2.;
-        let aa_sqrt: u8 = (aa_factor as f64).sqrt() as u8;
-        let aa: u8 = aa_sqrt*aa_sqrt;
-        let dus: Vec3 = du/(aa_sqrt + 1) as f64;
-        let dvs: Vec3 = dv/(aa_sqrt + 1) as f64;
-        let s_corner: Vec3 = v_corner + dus + dvs;
         let defocus_radius: f64 = focus_distance*((defocus_angle/2.).to_radians()).tan();
         let dudd: Vec3 = defocus_radius*i;
         let dvdd: Vec3 = defocus_radius*j;
 
-        Camera {
-            output_file: output_file,
-            look_at: look_at,
-            look_from: look_from,
-            up_dir: up_dir,
+        Ok(Camera {
+            output_file: self.output_file.unwrap_or_default(),
+            look_at,
+            look_from,
+            up_dir,
             d_angle: defocus_angle,
             focus_len: focus_distance,
-            max_d: max_depth,
-            vfov: theta,
+            projection,
             ar: aspect_ratio,
             w: image_width,
-            h: h,
-            vh: vh,
-            vw: vw,
-            center: center,
-            vu: vu,
-            vv: vv,
-            du: du,
-            dv: dv,
-            v_corner: v_corner,
-            aa_sqrt: aa_sqrt,
-            aa: aa,
-            dus: dus,
-            dvs: dvs,
-            s_corner: s_corner,
-            dudd: dudd,
-            dvdd: dvdd
-        }
-    }
-
-    pub fn render(&self, world: World) {
-        let mut img_matrix: Vec<Vec<Vec3>> = vec![];
+            h,
+            vh,
+            vw,
+            center,
+            i,
+            j,
+            k,
+            vu,
+            vv,
+            du,
+            dv,
+            v_corner,
+            spp: self.samples,
+            sampler: self.sampler,
+            filter: self.filter,
+            filter_radius: self.filter_radius.unwrap_or(self.filter.default_radius()),
+            adaptive: self.adaptive,
+            heatmap: self.heatmap,
+            pass_samples: self.pass_samples.or(self.checkpoint.as_ref().map(|_| PASS_SAMPLES)),
+            checkpoint: self.checkpoint,
+            resume: self.resume,
+            dudd,
+            dvdd,
+            shutter: Interval {min: self.shutter.0, max: self.shutter.1},
+            aovs: self.aovs,
+            denoiser: self.denoiser,
+            tone_map: self.tone_map,
+            exposure: self.exposure,
+            format,
+            threads: self.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+            seed: self.seed,
+            fingerprint,
+            integrator: Integrator {max_depth: self.max_depth, rr_depth: self.rr_depth}
+        })
+    }
+}
+
+impl Camera {
+    pub fn render(&self, world: World) { // In passes of pass_samples samples per pixel when progressive, checkpointing after each
+        let mut state: Checkpoint = self.resume.clone().unwrap_or_else(|| Checkpoint::new(self.w, self.h, self.seed, self.sampler, self.filter, self.filter_radius));
+        state.fingerprint = self.fingerprint;
+        state.spp = self.spp;
+        let pass_samples: u32 = self.pass_samples.unwrap_or(self.spp);
+        let passes_total: u32 = state.passes + self.spp.saturating_sub(state.samples).div_ceil(pass_samples);
+        while (state.samples < self.spp) {
+            let end: u32 = state.samples.saturating_add(pass_samples).min(self.spp);
+            self.trace(&world, &mut state, end, passes_total);
+            if (self.pass_samples.is_some()) {
+                self.write_preview(&state.film.resolve());
+                if let Some(path) = &self.checkpoint {
+                    if let Err(e) = state.write(path) {
+                        eprintln!("\nerror: can't write checkpoint {}", e);
+                    }
+                }
+            }
+        }
+        self.write_outputs(&state.film.resolve(), &state.aovs, &state.counts());
+    }
+
+    fn write_preview(&self, img: &[Vec3]) { // Beauty image alone, overwritten after every pass and by the full outputs at the end
+        let result: Result<(), String> = match self.format {
+            Format::Png => {
+                write_png(self.output_file.clone(), self.w, self.h, &self.display(img));
+                Ok(())
+            },
+            Format::Exr => write_exr(&self.output_file, self.w, self.h, &[Layer::rgb("beauty", img)]).map_err(|e| e.to_string()),
+            Format::Hdr => write_hdr(&self.output_file, self.w, self.h, img).map_err(|e| e.to_string()),
+            Format::Pfm => write_pfm(&self.output_file, self.w, self.h, &Layer::rgb("beauty", img)).map_err(|e| e.to_string())
+        };
+        if let Err(e) = result {
+            eprintln!("\nerror: can't write {}: {}", self.output_file, e);
+        }
+    }
+
+    fn write_outputs(&self, img: &[Vec3], aovs: &[Aovs], counts: &[u32]) {
+        let denoised: Option<Vec<Vec3>> = self.denoiser.map(|d| d.denoise(self.w, self.h, img, aovs)); // Written next to the raw image, not instead of it
+        if (self.format == Format::Png) {
+            write_png(self.output_file.clone(), self.w, self.h, &self.display(img));
+            if (self.aovs) {
+                write_aovs(&self.output_file, self.w, self.h, aovs);
+            }
+            if let Some(denoised) = denoised {
+                write_png(pass_path(&self.output_file, "denoised"), self.w, self.h, &self.display(&denoised));
+            }
+            if (self.heatmap) {
+                write_png_linear(pass_path(&self.output_file, "samples"), self.w, self.h, &adaptive::heatmap(counts, self.spp));
+            }
+            return;
+        }
+
+        let mut layers: Vec<Layer> = vec![Layer::rgb("beauty", img)];                // Float formats keep the linear radiance, before exposure and tone mapping
+        if let Some(denoised) = denoised {
+            layers.push(Layer::rgb("denoised", &denoised));
+        }
+        if (self.aovs) {
+            layers.extend(aov::layers(aovs));
+        }
+        if (self.heatmap) {
+            layers.push(Layer::gray("samples", "N", counts.iter().map(|n| *n as f32).collect()));
+        }
+        let result: Result<(), (String, String)> = match self.format {              // Path that failed and why
+            Format::Exr => write_exr(&self.output_file, self.w, self.h, &layers).map_err(|e| (self.output_file.clone(), e.to_string())),
+            _ => layers.iter().enumerate().try_for_each(|(k, layer)| {                // One file per layer, the beauty one at the output path
+                let path: String = if (k == 0) {self.output_file.clone()} else {pass_path(&self.output_file, &layer.name)};
+                match self.format {
+                    Format::Hdr => write_hdr(&path, self.w, self.h, &layer.to_rgb()).map_err(|e| e.to_string()),
+                    _ => write_pfm(&path, self.w, self.h, layer).map_err(|e| e.to_string())
+                }.map_err(|e| (path, e))
+            })
+        };
+        if let Err((path, e)) = result {
+            eprintln!("error: can't write {}: {}", path, e);
+        }
+    }
+
+    fn display(&self, img: &[Vec3]) -> Vec<Vec3> { // Exposed and tone mapped copy of img, ready for an 8-bit image
+        img.iter().map(|c| self.tone_map.apply(*c, self.exposure)).collect()
+    }
+
+    fn trace(&self, world: &World, state: &mut Checkpoint, end: u32, passes_total: u32) { // One pass over the whole image, taking every pixel that isn't done up to sample index end
+        let tiles_x: u32 = self.w.div_ceil(TILE_SIZE);
+        let tiles_y: u32 = self.h.div_ceil(TILE_SIZE);
+        let tiles_total: u32 = tiles_x*tiles_y;
+        let next_tile: AtomicU32 = AtomicU32::new(0);
+        let pixels_done: AtomicU32 = AtomicU32::new(0);
         let pixels_total: u32 = self.w*self.h;
-        let mut pixels_done: u32 = 0;
-        for j in 0..self.h {
-            img_matrix.push(vec![]);
-            for i in 0..self.w {
-                img_matrix[j as usize].push(self.get_px_color(&world, i, j));
-                pixels_done += 1;
-                print!("\r{:.2}%", 100.*pixels_done as f32/pixels_total as f32);
-                stdout().flush();
+        let prev: &Checkpoint = state;
+
+        let mut tiles: Vec<Tile> = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads).map(|_| s.spawn(|| {
+                let mut done: Vec<Tile> = vec![];
+                loop {
+                    let t: u32 = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if (t >= tiles_total) {
+                        break;
+                    }
+                    let tile: Tile = self.render_tile(world, prev, t, tiles_x, end);
+                    let n: u32 = pixels_done.fetch_add(tile.stats.len() as u32, Ordering::Relaxed) + tile.stats.len() as u32;
+                    print!("\r{:.2}%", 100.*(prev.passes as f32 + n as f32/pixels_total as f32)/passes_total as f32);
+                    stdout().flush();
+                    done.push(tile);
+                }
+                done
+            })).collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+        tiles.sort_by_key(|tile| tile.index);                                          // Overlapping margins are summed in tile order, so the result doesn't depend on which thread finished first
+
+        for tile in tiles {
+            state.film.merge(&tile.film);
+            let (x0, y0, x1, y1): (u32, u32, u32, u32) = self.tile_bounds(tile.index % tiles_x, tile.index / tiles_x);
+            let tw: u32 = x1 - x0;
+            for k in 0..tile.stats.len() {
+                let (i, j): (u32, u32) = (x0 + k as u32 % tw, y0 + k as u32 / tw);
+                let p: usize = (j*self.w + i) as usize;
+                state.stats[p] = tile.stats[k];
+                state.done[p] = tile.done[k];
+                if let Some(px_aovs) = tile.aovs[k] {
+                    state.aovs[p] = px_aovs;
+                }
             }
         }
-        write_png(self.output_file.clone(), img_matrix);
+        state.samples = end;
+        state.passes += 1;
+    }
+
+    fn tile_bounds(&self, tx: u32, ty: u32) -> (u32, u32, u32, u32) { // Pixel range [x0, x1) x [y0, y1) covered by a tile
+        let x0: u32 = tx*TILE_SIZE;
+        let y0: u32 = ty*TILE_SIZE;
+        (x0, y0, (x0 + TILE_SIZE).min(self.w), (y0 + TILE_SIZE).min(self.h))
     }
 
-    fn get_px_color(&self, world: &World, i: u32, j: u32) -> Vec3 {
-        let mut px_color: Vec3 = Vec3(0., 0., 0.);
-        let start: Vec3 = self.s_corner + (i as f64)*self.du + (j as f64)*self.dv;
-        for pi in 0..self.aa_sqrt {
-            for pj in 0..self.aa_sqrt {
-                let pos: Vec3 = start + (pi as f64)*self.dus + (pj as f64)*self.dvs;
-                let ray_ori: Vec3 = if (self.d_angle <= 0.) {
-                    self.center
-                } else {
-                    self.defocus_disk_sample()
-                };
-                let r: Ray = Ray {ori: ray_ori, dir: pos - ray_ori};
-                px_color += Self::ray_color(r, self.max_d, &world)/self.aa as f64;
+    fn render_tile(&self, world: &World, prev: &Checkpoint, index: u32, tiles_x: u32, end: u32) -> Tile {
+        let (x0, y0, x1, y1): (u32, u32, u32, u32) = self.tile_bounds(index % tiles_x, index / tiles_x);
+        let margin: u32 = (self.filter_radius + 0.5).ceil() as u32;
+        let mut tile: Tile = Tile {
+            index,
+            film: Film::new(x0 as i64 - margin as i64, y0 as i64 - margin as i64, x1 - x0 + 2*margin, y1 - y0 + 2*margin),
+            aovs: Vec::with_capacity(((x1 - x0)*(y1 - y0)) as usize),
+            stats: Vec::with_capacity(((x1 - x0)*(y1 - y0)) as usize),
+            done: Vec::with_capacity(((x1 - x0)*(y1 - y0)) as usize)
+        };
+        let mut sampler: Sampler = Sampler::new(self.sampler, self.spp, self.seed);
+        for j in y0..y1 {
+            for i in x0..x1 {
+                let p: usize = (j*self.w + i) as usize;
+                if (prev.done[p]) {
+                    tile.aovs.push(None);
+                    tile.stats.push(prev.stats[p]);
+                    tile.done.push(true);
+                    continue;
+                }
+                let (px_aovs, stats, done): (Aovs, Stats, bool) = self.sample_pixel(world, &mut sampler, &mut tile.film, (i, j), prev.stats[p], end);
+                tile.aovs.push(if (prev.stats[p].n == 0) {Some(px_aovs)} else {None}); // Passes only come from a pixel's first samples, later ones would barely change them
+                tile.stats.push(stats);
+                tile.done.push(done);
             }
         }
-        px_color
+        tile
     }
 
-    fn ray_color(r: Ray, depth: u8, world: &World) -> Vec3 {
-        if (depth <= 0) {
-            return Vec3(0. ,0., 0.);
+    fn sample_pixel(&self, world: &World, sampler: &mut Sampler, film: &mut Film, (i, j): (u32, u32), mut stats: Stats, end: u32) -> (Aovs, Stats, bool) { // Splats the pixel's samples from stats.n up to end onto film, returns the passes averaged over them, the updated stats and whether adaptive sampling stopped the pixel
+        let mut sum: AovSum = AovSum::default();
+        let mut next_check: u32 = self.adaptive.map_or(u32::MAX, |a| a.min_samples);
+        while (next_check <= stats.n) {                                             // Picks up the schedule where an earlier pass left it
+            next_check = next_check.saturating_mul(2);
         }
+        for index in stats.n..end {
+            sampler.start_pixel_sample(i, j, index);
+            let (dx, dy): (f64, f64) = sampler.get_2d();                            // Position within the pixel first, where stratification pays off the most
+            let lens: (f64, f64) = sampler.get_2d();
+            let time: f64 = self.shutter.min + sampler.get_1d()*self.shutter.size();
+            let (x, y): (f64, f64) = (i as f64 + dx, j as f64 + dy);
+            let mut aovs: Aovs = Aovs::miss();
+            let mut color: Vec3 = Vec3(0., 0., 0.);
+            if let Some(r) = self.get_ray(x, y, lens, time) {
+                color = self.integrator.ray_color(r, world, &mut aovs, sampler);
+                if (aovs.object.is_some()) {
+                    aovs.depth = match self.projection {                            // Along the viewing axis where there is one, straight distance otherwise
+                        Projection::Perspective {..} | Projection::Orthographic {..} => (aovs.position - r.ori)*(-self.k),
+                        _ => (aovs.position - r.ori).len()
+                    };
+                }
+            }
+            film.add_sample(x, y, color, self.filter, self.filter_radius);
+            sum.add(&aovs);
+            stats.add(color);
+            if (index + 1 == next_check) {                                          // Checks at min_samples and every doubling after it, the counts where Sobol points are best spread
+                if (self.adaptive.is_some_and(|a| a.converged(&stats))) {
+                    return (sum.average(), stats, true);
+                }
+                next_check = next_check.saturating_mul(2);
+            }
+        }
+        (sum.average(), stats, false)
+    }
 
-        let mut rec: HitRecord = HitRecord::new_empty();
-        if (world.hit(r, Interval{min: 0.000001, max: f64::INFINITY}, &mut rec)) {
-            let (r_out, color): (Ray, Vec3) = rec.mat.scatter(r, &rec); 
-            if (!color.near_zero()) {
-                color.coord_mul(Self::ray_color(r_out, depth-1, world))
-            } else {
-                Vec3(0., 0., 0.)
+    fn get_ray(&self, x: f64, y: f64, lens: (f64, f64), time: f64) -> Option<Ray> { // Ray through the image point (x, y), in pixels from the top-left corner, or None outside the projection's image
+        match self.projection {
+            Projection::Perspective {..} => {
+                let pos: Vec3 = self.v_corner + x*self.du + y*self.dv;
+                let ray_ori: Vec3 = self.center + self.defocus_disk_sample(lens);
+                Some(Ray {ori: ray_ori, dir: pos - ray_ori, time})
+            },
+            Projection::Orthographic {..} => {
+                let pos: Vec3 = self.v_corner + x*self.du + y*self.dv;
+                let ray_ori: Vec3 = pos + self.focus_len*self.k + self.defocus_disk_sample(lens); // Straight back from the focus plane to the camera plane
+                Some(Ray {ori: ray_ori, dir: pos - ray_ori, time})
+            },
+            Projection::Equirectangular => {
+                let phi: f64 = 2.*PI*(x/self.w as f64 - 0.5);                        // Longitude, zero straight ahead
+                let lat: f64 = PI*(0.5 - y/self.h as f64);
+                let dir: Vec3 = lat.cos()*phi.sin()*self.i + lat.sin()*self.j - lat.cos()*phi.cos()*self.k;
+                Some(Ray {ori: self.center, dir, time})
+            },
+            Projection::Fisheye {fov} => {
+                let half: f64 = 0.5*self.w.min(self.h) as f64;
+                let nx: f64 = (x - 0.5*self.w as f64)/half;
+                let ny: f64 = (0.5*self.h as f64 - y)/half;
+                let r: f64 = (nx*nx + ny*ny).sqrt();
+                if (r > 1.) {
+                    return None;                                                    // Outside the image circle
+                }
+                let theta: f64 = r*fov.to_radians()/2.;                             // Equidistant: angle from the axis grows linearly with the radius
+                let phi: f64 = ny.atan2(nx);
+                let dir: Vec3 = theta.sin()*(phi.cos()*self.i + phi.sin()*self.j) - theta.cos()*self.k;
+                Some(Ray {ori: self.center, dir, time})
             }
-        } else {
-            let unit_dir: Vec3 = r.dir.unit();
-            let a: f64 = 0.5*(unit_dir.1 + 1.0);
-            (1.0 - a)*Vec3(1.0, 1.0, 1.0) + a*Vec3(0.5, 0.7, 1.0)
         }
     }
 
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let v: Vec3 = Vec3::random_in_unit_disk();
-        self.center + v.0*self.dudd + v.1*self.dvdd
+    fn defocus_disk_sample(&self, u: (f64, f64)) -> Vec3 { // Offset of the ray origin within the lens, zero for a pinhole
+        if (self.d_angle <= 0.) {
+            return Vec3(0., 0., 0.);
+        }
+        let v: Vec3 = Vec3::concentric_disk(u);
+        v.0*self.dudd + v.1*self.dvdd
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::scene::{Scene, load_scene};
+
+    fn render_film(seed: u64, threads: usize) -> Vec<(u64, u64, u64)> { // Noise, marble and wood textures included, the scene is rebuilt every time
+        let scene: Scene = load_scene(Path::new("scenes/textures.toml"), Some(seed)).unwrap();
+        let camera: Camera = scene.camera.image_width(48).samples(4).threads(threads).build().unwrap();
+        let mut state: Checkpoint = Checkpoint::new(camera.w, camera.h, camera.seed, camera.sampler, camera.filter, camera.filter_radius);
+        camera.trace(&scene.world, &mut state, camera.spp, 1);
+        state.film.resolve().iter().map(|c| (c.0.to_bits(), c.1.to_bits(), c.2.to_bits())).collect()
+    }
+
+    #[test]
+    fn same_seed_renders_identically() {
+        let reference: Vec<(u64, u64, u64)> = render_film(7, 1);
+        assert!(render_film(7, 1) == reference);
+        assert!(render_film(7, 4) == reference);
+        assert!(render_film(8, 4) != reference);
+    }
+
+    #[test]
+    fn validate_rejects_degenerate_cameras() {
+        let builder = || CameraBuilder::new().look_from(Vec3(0., 0., 5.)).look_at(Vec3(0., 0., 0.)).output_file(String::from("out.png"));
+        assert!(builder().validate().is_ok());
+        assert!(matches!(builder().image_width(0).validate(), Err(CameraError::ZeroWidth)));
+        assert!(matches!(builder().image_width(4).aspect_ratio(16.).validate(), Err(CameraError::ZeroHeight {..})));
+        assert!(matches!(builder().aspect_ratio(f64::NAN).validate(), Err(CameraError::AspectRatio(_))));
+        assert!(matches!(builder().look_at(Vec3(0., 0., 5.)).validate(), Err(CameraError::LookAtLookFrom)));
+        assert!(matches!(builder().look_from(Vec3(1e-9, 0., 0.)).look_at(Vec3(0., 0., 0.)).up(Vec3(0., 0., 1.)).validate(), Ok(())));
+        assert!(matches!(builder().look_from(Vec3(1e9, 0., 0.)).look_at(Vec3(1e9 + 1e-6, 0., 0.)).validate(), Err(CameraError::LookAtLookFrom)));
+        assert!(matches!(builder().up(Vec3(0., 0., 1.)).validate(), Err(CameraError::UpParallel)));
+        assert!(matches!(builder().look_from(Vec3(f64::NAN, 0., 5.)).validate(), Err(CameraError::NotFinite("look_from"))));
+        assert!(matches!(builder().up(Vec3(0., f64::INFINITY, 0.)).validate(), Err(CameraError::NotFinite("up_dir"))));
+        assert!(matches!(builder().shutter(0., f64::NAN).validate(), Err(CameraError::NotFinite("shutter"))));
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::vec3::Vec3;
+use crate::film::{Film, PixelFilter, NAMES as FILTERS};
+use crate::sampler::{SamplerKind, NAMES as SAMPLERS};
+use crate::adaptive::Stats;
+use crate::aov::Aovs;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 2;
+const HEADER_BYTES: u64 = 4 + 4 + 4 + 4 + 8 + 8 + 4 + 1 + 1 + 8 + 4 + 4; // Magic, version, size, seed, fingerprint, spp, sampler, filter, radius, samples, passes
+const PIXEL_BYTES: u64 = 6*8 + 4 + 1 + 10*8 + 8;                          // Film, stats, done flag, AOVs
+
+#[derive(Clone)]
+pub struct Checkpoint { // Everything a render accumulated so far, enough to carry on adding samples where it stopped
+    pub w: u32,
+    pub h: u32,
+    pub seed: u64,              // With the pixel and sample index, fully determines every random number, so it is the whole generator state
+    pub fingerprint: u64,       // Of the scene, camera and integrator settings
+    pub spp: u32,               // Samples per pixel the render was aiming for, which the stratified sampler lays its strata out over
+    pub sampler: SamplerKind,
+    pub filter: PixelFilter,
+    pub filter_radius: f64,
+    pub samples: u32,           // Sample index every pixel has reached, unless it converged before
+    pub passes: u32,
+    pub film: Film,             // Filter-weighted sums over the whole image
+    pub stats: Vec<Stats>,      // Row-major, the sample counts are their n
+    pub done: Vec<bool>,        // Row-major, whether adaptive sampling stopped the pixel
+    pub aovs: Vec<Aovs>         // Row-major, from the first pass
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, std::io::Error),
+    Format(PathBuf, String)     // Not a checkpoint, or from an incompatible version
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            CheckpointError::Format(path, msg) => write!(f, "{}: {}", path.display(), msg)
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl Checkpoint {
+    pub fn new(w: u32, h: u32, seed: u64, sampler: SamplerKind, filter: PixelFilter, filter_radius: f64) -> Checkpoint { // Nothing rendered yet
+        let n: usize = (w*h) as usize;
+        Checkpoint {
+            w, h, seed, sampler, filter, filter_radius,
+            fingerprint: 0,
+            spp: 0,
+            samples: 0,
+            passes: 0,
+            film: Film::new(0, 0, w, h),
+            stats: vec![Stats::default(); n],
+            done: vec![false; n],
+            aovs: vec![Aovs::miss(); n]
+        }
+    }
+
+    pub fn counts(&self) -> Vec<u32> {
+        self.stats.iter().map(|s| s.n).collect()
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), CheckpointError> { // Through a temporary file synced to disk before it replaces the old one, so a crash or power loss leaves either checkpoint intact
+        let tmp: String = format!("{}.tmp", path);
+        let io_err = |e: std::io::Error| CheckpointError::Io(PathBuf::from(path), e);
+        let mut out: BufWriter<File> = BufWriter::new(File::create(&tmp).map_err(io_err)?);
+        let mut bytes: Vec<u8> = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.w.to_le_bytes());
+        bytes.extend_from_slice(&self.h.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&self.spp.to_le_bytes());
+        bytes.push(self.sampler as u8);
+        bytes.push(self.filter as u8);
+        bytes.extend_from_slice(&self.filter_radius.to_le_bytes());
+        bytes.extend_from_slice(&self.samples.to_le_bytes());
+        bytes.extend_from_slice(&self.passes.to_le_bytes());
+        out.write_all(&bytes).map_err(io_err)?;
+        for k in 0..(self.w*self.h) as usize {                                      // Little-endian fields one pixel after the other
+            let mut px: Vec<u8> = Vec::with_capacity(128);
+            let (s, a): (Stats, Aovs) = (self.stats[k], self.aovs[k]);
+            for x in [self.film.sum[k].0, self.film.sum[k].1, self.film.sum[k].2, self.film.weight[k], s.mean, s.m2] {
+                px.extend_from_slice(&x.to_le_bytes());
+            }
+            px.extend_from_slice(&s.n.to_le_bytes());
+            px.push(self.done[k] as u8);
+            for x in [a.depth, a.normal.0, a.normal.1, a.normal.2, a.albedo.0, a.albedo.1, a.albedo.2, a.position.0, a.position.1, a.position.2] {
+                px.extend_from_slice(&x.to_le_bytes());
+            }
+            px.extend_from_slice(&a.object.map_or(-1, |id| id as i64).to_le_bytes());
+            out.write_all(&px).map_err(io_err)?;
+        }
+        out.flush().map_err(io_err)?;
+        out.get_ref().sync_all().map_err(io_err)?;
+        drop(out);
+        fs::rename(&tmp, path).map_err(io_err)?;
+        sync_dir(Path::new(path)).map_err(io_err)                                   // Makes the rename itself durable
+    }
+
+    pub fn read(path: &str) -> Result<Checkpoint, CheckpointError> {
+        let file: File = File::open(path).map_err(|e| CheckpointError::Io(PathBuf::from(path), e))?;
+        let len: u64 = file.metadata().map_err(|e| CheckpointError::Io(PathBuf::from(path), e))?.len();
+        let mut r: Reader = Reader {path: Path::new(path), inner: BufReader::new(file)};
+        let magic: [u8; 4] = r.bytes()?;
+        if (&magic != MAGIC) {
+            return Err(r.err("not a checkpoint file"));
+        }
+        let version: u32 = r.u32()?;
+        if (version != VERSION) {
+            return Err(r.err(&format!("checkpoint version {} is not supported, expected {}", version, VERSION)));
+        }
+        let (w, h, seed): (u32, u32, u64) = (r.u32()?, r.u32()?, r.u64()?);
+        let (fingerprint, spp): (u64, u32) = (r.u64()?, r.u32()?);
+        let expected: Option<u64> = w.checked_mul(h).and_then(|n| (n as u64).checked_mul(PIXEL_BYTES)).and_then(|n| n.checked_add(HEADER_BYTES));
+        if (expected != Some(len)) {                                                // Checked before allocating anything the header asks for, this also catches truncated files and trailing bytes
+            return Err(r.err(&format!("{} bytes don't hold a {}x{} image", len, w, h)));
+        }
+        let sampler: SamplerKind = SAMPLERS.get(r.u8()? as usize).and_then(|name| SamplerKind::from_name(name)).ok_or_else(|| r.err("unknown sampler"))?;
+        let filter: PixelFilter = FILTERS.get(r.u8()? as usize).and_then(|name| PixelFilter::from_name(name)).ok_or_else(|| r.err("unknown filter"))?;
+        let filter_radius: f64 = r.f64()?;
+        let mut cp: Checkpoint = Checkpoint::new(w, h, seed, sampler, filter, filter_radius);
+        cp.fingerprint = fingerprint;
+        cp.spp = spp;
+        cp.samples = r.u32()?;
+        cp.passes = r.u32()?;
+        for k in 0..(w*h) as usize {
+            cp.film.sum[k] = Vec3(r.f64()?, r.f64()?, r.f64()?);
+            cp.film.weight[k] = r.f64()?;
+            let (mean, m2): (f64, f64) = (r.f64()?, r.f64()?);
+            cp.stats[k] = Stats {n: r.u32()?, mean, m2};
+            cp.done[k] = r.u8()? != 0;
+            let depth: f64 = r.f64()?;
+            let normal: Vec3 = Vec3(r.f64()?, r.f64()?, r.f64()?);
+            let albedo: Vec3 = Vec3(r.f64()?, r.f64()?, r.f64()?);
+            let position: Vec3 = Vec3(r.f64()?, r.f64()?, r.f64()?);
+            let object: i64 = r.u64()? as i64;
+            cp.aovs[k] = Aovs {depth, normal, albedo, position, object: if (object < 0) {None} else {Some(object as usize)}};
+        }
+        Ok(cp)
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    let dir: &Path = match path.parent() {
+        Some(p) if (!p.as_os_str().is_empty()) => p,
+        _ => Path::new(".")
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(path: &Path) -> std::io::Result<()> { // Directories can't be opened as files here, the rename is as durable as the platform makes it
+    Ok(())
+}
+
+struct Reader<'a> {
+    path: &'a Path,
+    inner: BufReader<File>
+}
+
+impl Reader<'_> {
+    fn err(&self, msg: &str) -> CheckpointError {
+        CheckpointError::Format(self.path.to_path_buf(), msg.to_string())
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], CheckpointError> {
+        let mut buf: [u8; N] = [0; N];
+        self.inner.read_exact(&mut buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => self.err("truncated checkpoint"),
+            _ => CheckpointError::Io(self.path.to_path_buf(), e)
+        })?;
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> Result<u8, CheckpointError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CheckpointError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CheckpointError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, CheckpointError> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn sample() -> Checkpoint { // Distinct values in every field, so a swapped or dropped one shows
+        let mut cp: Checkpoint = Checkpoint::new(3, 2, 42, SamplerKind::Halton, PixelFilter::Mitchell, 1.75);
+        cp.fingerprint = 0xdeadbeef;
+        cp.spp = 64;
+        cp.samples = 32;
+        cp.passes = 2;
+        for k in 0..6 {
+            let x: f64 = k as f64;
+            cp.film.sum[k] = Vec3(x, x + 0.25, -x);
+            cp.film.weight[k] = 0.5*x;
+            cp.stats[k] = Stats {n: k as u32 + 1, mean: x/7., m2: x*x};
+            cp.done[k] = k % 2 == 0;
+            cp.aovs[k] = Aovs {depth: x + 1., normal: Vec3(0., x, 1.), albedo: Vec3(x, 0.5, 0.), position: Vec3(-x, x, 2.*x), object: if (k == 3) {None} else {Some(k)}};
+        }
+        cp
+    }
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("raytracer-test-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path: String = temp_path("round-trip.ck");
+        let cp: Checkpoint = sample();
+        cp.write(&path).unwrap();
+        let read: Checkpoint = Checkpoint::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!((read.w, read.h, read.seed, read.fingerprint, read.spp, read.samples, read.passes) == (cp.w, cp.h, cp.seed, cp.fingerprint, cp.spp, cp.samples, cp.passes));
+        assert!(read.sampler == cp.sampler && read.filter == cp.filter && read.filter_radius == cp.filter_radius);
+        for k in 0..6 {
+            let (a, b): (Vec3, Vec3) = (read.film.sum[k], cp.film.sum[k]);
+            assert!((a.0, a.1, a.2, read.film.weight[k]) == (b.0, b.1, b.2, cp.film.weight[k]));
+            assert!((read.stats[k].n, read.stats[k].mean, read.stats[k].m2) == (cp.stats[k].n, cp.stats[k].mean, cp.stats[k].m2));
+            assert!(read.done[k] == cp.done[k]);
+            let (a, b): (Aovs, Aovs) = (read.aovs[k], cp.aovs[k]);
+            assert!(a.depth == b.depth && a.object == b.object);
+            for (u, v) in [(a.normal, b.normal), (a.albedo, b.albedo), (a.position, b.position)] {
+                assert!((u.0, u.1, u.2) == (v.0, v.1, v.2));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_files() {
+        let path: String = temp_path("truncated.ck");
+        sample().write(&path).unwrap();
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        for len in [0, 3, 20, HEADER_BYTES as usize, bytes.len() - 1] {
+            fs::write(&path, &bytes[..len]).unwrap();
+            assert!(matches!(Checkpoint::read(&path), Err(CheckpointError::Format(..))), "accepted {} of {} bytes", len, bytes.len());
+        }
+        fs::write(&path, [bytes.as_slice(), &[0]].concat()).unwrap();
+        assert!(matches!(Checkpoint::read(&path), Err(CheckpointError::Format(..))));
+        fs::remove_file(&path).unwrap();
+    }
+}
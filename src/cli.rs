@@ -0,0 +1,286 @@
+use std::fmt;
+
+use crate::camera::CameraBuilder;
+use crate::denoise::Denoiser;
+use crate::tonemap::{ToneMap, NAMES};
+use crate::color::{Format, FORMATS};
+use crate::sampler::{SamplerKind, NAMES as SAMPLERS};
+use crate::film::{PixelFilter, NAMES as FILTERS, MIN_RADIUS};
+use crate::adaptive::Adaptive;
+
+pub const USAGE: &str = "\
+Usage: raytracer [render] <SCENE> [OPTIONS]
+       raytracer scenes
+       raytracer help
+
+Commands:
+  render    Render a scene file or built-in scene (default)
+  scenes    List the built-in scenes
+  help      Print this message
+
+Options:
+      --scene <SCENE>     Scene file (.toml) or built-in scene name, instead of the positional argument
+  -o, --output <PATH>     Output image, overrides render.output
+  -w, --width <PX>        Image width in pixels
+  -a, --aspect <RATIO>    Aspect ratio, either a number (1.78) or W:H (16:9)
+  -s, --samples <N>       Samples per pixel
+      --sampler <NAME>    Sample generator: independent, stratified, halton or sobol (default)
+      --filter <NAME>     Pixel reconstruction filter: box (default), tent, gaussian, mitchell or lanczos
+      --filter-radius <R> Filter radius in pixels, at least 0.5, by default 0.5, 1, 1.5, 2 and 3 for the filters above
+      --adaptive          Stop sampling pixels whose estimated error is low enough, --samples being the most any pixel gets
+      --min-samples <N>   Samples every pixel takes before the first adaptive check (default 16)
+      --threshold <E>     Relative error at which adaptive sampling stops (default 0.05)
+      --heatmap           Also write the samples taken per pixel (out.samples.png)
+      --checkpoint <PATH> Render progressively, saving progress to PATH after every pass
+      --pass-samples <N>  Render progressively in passes of N samples per pixel (default 16 with a checkpoint)
+      --resume <PATH>     Carry on from a checkpoint up to --samples, checkpointing to it unless --checkpoint is given
+  -d, --depth <N|none>    Maximum number of bounces, none leaves it to Russian roulette
+      --seed <N>          Seed for scene generation and the per-pixel sample streams (default 0)
+  -t, --threads <N>       Number of worker threads
+      --aovs              Also write depth, normal, albedo, position and object passes (out.depth.png, ...)
+      --denoise           Also write a denoised copy of the image (out.denoised.png)
+      --tonemap <OP>      Tone mapping operator: clamp, reinhard, reinhard-extended, hable or aces
+      --white <L>         Luminance mapped to white by reinhard-extended (default 4)
+      --exposure <EV>     Exposure compensation in stops, applied before tone mapping
+      --format <FMT>      png, or exr, hdr or pfm for linear float output, instead of guessing from the extension
+  -h, --help              Print this message
+";
+
+pub enum Command {
+    Render(Box<RenderArgs>), // Boxed, it's by far the largest
+    ListScenes,
+    Help
+}
+
+#[derive(Default)]
+pub struct RenderArgs { // Values given on the command line, each overriding the scene's own
+    pub scene: String,
+    pub output: Option<String>,
+    pub width: Option<u32>,
+    pub aspect: Option<f64>,
+    pub samples: Option<u32>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<PixelFilter>,
+    pub filter_radius: Option<f64>,
+    pub adaptive: Option<Adaptive>,
+    pub heatmap: bool,
+    pub checkpoint: Option<String>,
+    pub pass_samples: Option<u32>,
+    pub resume: Option<String>,
+    pub depth: Option<Option<u32>>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub aovs: bool,
+    pub denoise: bool,
+    pub tone_map: Option<ToneMap>,
+    pub exposure: Option<f64>,
+    pub format: Option<Format>
+}
+
+#[derive(Debug)]
+pub struct CliError(pub String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+pub fn parse(args: &[String]) -> Result<Command, CliError> { // args excludes the program name
+    let rest: &[String] = match args.first().map(|a| a.as_str()) {
+        None => return Err(CliError(String::from("no scene given"))),
+        Some("help") => return Ok(Command::Help),
+        Some("scenes") => {
+            if let Some(extra) = args.get(1) {
+                return Err(CliError(format!("unexpected argument '{}' for scenes", extra)));
+            }
+            return Ok(Command::ListScenes);
+        },
+        Some("render") => &args[1..],
+        Some(_) => args
+    };
+
+    let mut r: RenderArgs = RenderArgs::default();
+    let mut scene: Option<String> = None;
+    let mut tone_map: Option<String> = None;
+    let mut white: Option<f64> = None;
+    let mut adaptive: bool = false;
+    let mut min_samples: Option<u32> = None;
+    let mut threshold: Option<f64> = None;
+    let mut it = rest.iter();
+    while let Some(arg) = it.next() {
+        let (flag, inline): (&str, Option<&str>) = match arg.split_once('=') { // --width=800
+            Some((flag, value)) if (arg.starts_with("--")) => (flag, Some(value)),
+            _ => (arg.as_str(), None)
+        };
+        let mut value = || -> Result<String, CliError> {
+            match inline {
+                Some(v) => Ok(v.to_string()),
+                None => it.next().cloned().ok_or_else(|| CliError(format!("{} needs a value", flag)))
+            }
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--scene" => scene = Some(value()?),
+            "-o" | "--output" => r.output = Some(value()?),
+            "-w" | "--width" => r.width = Some(positive(flag, &value()?)?),
+            "-a" | "--aspect" => r.aspect = Some(aspect(&value()?)?),
+            "-s" | "--samples" => r.samples = Some(positive(flag, &value()?)?),
+            "--sampler" => {
+                let v: String = value()?;
+                r.sampler = Some(SamplerKind::from_name(&v).ok_or_else(|| CliError(format!("invalid value '{}' for --sampler: expected one of {}", v, SAMPLERS.join(", "))))?);
+            },
+            "--filter" => {
+                let v: String = value()?;
+                r.filter = Some(PixelFilter::from_name(&v).ok_or_else(|| CliError(format!("invalid value '{}' for --filter: expected one of {}", v, FILTERS.join(", "))))?);
+            },
+            "--filter-radius" => {
+                let v: String = value()?;
+                r.filter_radius = Some(v.parse::<f64>().ok().filter(|r| r.is_finite() && *r >= MIN_RADIUS)
+                    .ok_or_else(|| CliError(format!("invalid value '{}' for --filter-radius: expected a number of pixels no smaller than {}", v, MIN_RADIUS)))?);
+            },
+            "--adaptive" => adaptive = true,
+            "--min-samples" => min_samples = Some(positive(flag, &value()?)?),
+            "--threshold" => {
+                let v: String = value()?;
+                threshold = Some(v.parse::<f64>().ok().filter(|t| t.is_finite() && *t > 0.)
+                    .ok_or_else(|| CliError(format!("invalid value '{}' for --threshold: expected a positive number", v)))?);
+            },
+            "--heatmap" => r.heatmap = true,
+            "--checkpoint" => r.checkpoint = Some(value()?),
+            "--pass-samples" => r.pass_samples = Some(positive(flag, &value()?)?),
+            "--resume" => r.resume = Some(value()?),
+            "-d" | "--depth" => {
+                let v: String = value()?;
+                r.depth = Some(if (v == "none") {None} else {Some(positive(flag, &v)?)});
+            },
+            "--seed" => {
+                let v: String = value()?;
+                r.seed = Some(v.parse().map_err(|_| CliError(format!("invalid value '{}' for --seed: expected a non-negative integer", v)))?);
+            },
+            "-t" | "--threads" => r.threads = Some(positive(flag, &value()?)?),
+            "--aovs" => r.aovs = true,
+            "--denoise" => r.denoise = true,
+            "--tonemap" => tone_map = Some(value()?),
+            "--format" => {
+                let v: String = value()?;
+                r.format = Some(Format::from_name(&v).ok_or_else(|| CliError(format!("invalid value '{}' for --format: expected one of {}", v, FORMATS.join(", "))))?);
+            },
+            "--white" => {
+                let v: String = value()?;
+                white = Some(v.parse::<f64>().ok().filter(|l| l.is_finite() && *l > 0.)
+                    .ok_or_else(|| CliError(format!("invalid value '{}' for --white: expected a positive number", v)))?);
+            },
+            "--exposure" => {
+                let v: String = value()?;
+                r.exposure = Some(v.parse::<f64>().ok().filter(|e| e.is_finite())
+                    .ok_or_else(|| CliError(format!("invalid value '{}' for --exposure: expected a number of stops", v)))?);
+            },
+            _ if (flag.starts_with('-') && flag.len() > 1) => return Err(CliError(format!("unknown option '{}'", flag))),
+            _ => {
+                if let Some(first) = &scene {
+                    return Err(CliError(format!("unexpected argument '{}', the scene is already '{}'", arg, first)));
+                }
+                scene = Some(arg.clone());
+            }
+        }
+    }
+    if let Some(name) = tone_map {
+        r.tone_map = Some(ToneMap::from_name(&name, white.unwrap_or(4.))
+            .ok_or_else(|| CliError(format!("invalid value '{}' for --tonemap: expected one of {}", name, NAMES.join(", "))))?);
+    }
+    if (white.is_some() && !matches!(r.tone_map, Some(ToneMap::ExtendedReinhard {..}))) {
+        return Err(CliError(String::from("--white only applies to --tonemap reinhard-extended")));
+    }
+    if (adaptive) {
+        let default: Adaptive = Adaptive::default();
+        r.adaptive = Some(Adaptive {min_samples: min_samples.unwrap_or(default.min_samples), threshold: threshold.unwrap_or(default.threshold)});
+    } else if (min_samples.is_some() || threshold.is_some()) {
+        return Err(CliError(String::from("--min-samples and --threshold only apply with --adaptive")));
+    }
+    r.scene = scene.ok_or_else(|| CliError(String::from("no scene given")))?;
+    Ok(Command::Render(Box::new(r)))
+}
+
+fn positive<T: std::str::FromStr + PartialOrd + Default>(flag: &str, v: &str) -> Result<T, CliError> {
+    match v.parse::<T>() {
+        Ok(n) if (n > T::default()) => Ok(n),
+        _ => Err(CliError(format!("invalid value '{}' for {}: expected a positive integer", v, flag)))
+    }
+}
+
+fn aspect(v: &str) -> Result<f64, CliError> {
+    let ratio: Option<f64> = match v.split_once(':') {
+        Some((w, h)) => w.parse::<f64>().ok().zip(h.parse::<f64>().ok()).map(|(w, h)| w/h),
+        None => v.parse::<f64>().ok()
+    };
+    match ratio {
+        Some(ratio) if (ratio.is_finite() && ratio > 0.) => Ok(ratio),
+        _ => Err(CliError(format!("invalid value '{}' for --aspect: expected a positive number or W:H", v)))
+    }
+}
+
+impl RenderArgs {
+    pub fn apply(&self, mut camera: CameraBuilder) -> CameraBuilder { // Overrides the scene's settings with the ones given here
+        if let Some(output) = &self.output {
+            camera = camera.output_file(output.clone());
+        }
+        if let Some(width) = self.width {
+            camera = camera.image_width(width);
+        }
+        if let Some(aspect) = self.aspect {
+            camera = camera.aspect_ratio(aspect);
+        }
+        if let Some(samples) = self.samples {
+            camera = camera.samples(samples);
+        }
+        if let Some(sampler) = self.sampler {
+            camera = camera.sampler(sampler);
+        }
+        if let Some(filter) = self.filter {
+            camera = camera.filter(filter);
+        }
+        if let Some(radius) = self.filter_radius {
+            camera = camera.filter_radius(radius);
+        }
+        if let Some(adaptive) = self.adaptive {
+            camera = camera.adaptive(adaptive);
+        }
+        if (self.heatmap) {
+            camera = camera.heatmap(true);
+        }
+        if let Some(path) = self.checkpoint.as_ref().or(self.resume.as_ref()) {
+            camera = camera.checkpoint(path.clone());
+        }
+        if let Some(pass_samples) = self.pass_samples {
+            camera = camera.progressive(pass_samples);
+        }
+        if let Some(depth) = self.depth {
+            camera = camera.max_depth(depth);
+        }
+        if let Some(seed) = self.seed {
+            camera = camera.seed(seed);
+        }
+        if let Some(threads) = self.threads {
+            camera = camera.threads(threads);
+        }
+        if (self.aovs) {
+            camera = camera.aovs(true);
+        }
+        if (self.denoise) {
+            camera = camera.denoiser(Denoiser::default());
+        }
+        if let Some(tone_map) = self.tone_map {
+            camera = camera.tone_map(tone_map);
+        }
+        if let Some(exposure) = self.exposure {
+            camera = camera.exposure(exposure);
+        }
+        if let Some(format) = self.format {
+            camera = camera.format(format);
+        }
+        camera
+    }
+}
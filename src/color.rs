@@ -1,34 +1,159 @@
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env::current_dir;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use image::{RgbImage, ImageBuffer, Rgb};
+use image::codecs::hdr::HdrEncoder;
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer as ExrLayer, LayerAttributes, SmallVec, WritableImage};
 
 use crate::vec3::Vec3;
 use crate::interval::Interval;
 
 pub fn write_ppm<W: std::io::Write>(writer: &mut BufWriter<W>, v: Vec3) {
     let intensity: Interval = Interval{min: 0., max: 0.999};
-    let r: u8 = (256.*intensity.clamp(v.0).sqrt()) as u8;
-    let g: u8 = (256.*intensity.clamp(v.1).sqrt()) as u8;
-    let b: u8 = (256.*intensity.clamp(v.2).sqrt()) as u8;
+    let r: u8 = (256.*linear_to_srgb(intensity.clamp(v.0))) as u8;
+    let g: u8 = (256.*linear_to_srgb(intensity.clamp(v.1))) as u8;
+    let b: u8 = (256.*linear_to_srgb(intensity.clamp(v.2))) as u8;
     writeln!(writer, "{} {} {}", r, g, b).expect("Writing failed")
 }
 
-pub fn write_png(path: String, img_matrix: Vec<Vec<Vec3>>) {
-    let h: u32 = img_matrix.len() as u32;
-    let w: u32 = img_matrix[0].len() as u32;
-    let mut img: RgbImage = ImageBuffer::new(w, h);
+pub fn write_png(path: String, w: u32, h: u32, img: &[Vec3]) { // img holds w*h display colors in [0, 1], row-major, stored sRGB-encoded
+    save_png(path, w, h, img, linear_to_srgb);
+}
+
+pub fn write_png_linear(path: String, w: u32, h: u32, img: &[Vec3]) { // Same as write_png but without the transfer function, for data rather than colors
+    save_png(path, w, h, img, |x| x);
+}
+
+fn save_png(path: String, w: u32, h: u32, img: &[Vec3], encode: fn(f64) -> f64) {
+    let mut png: RgbImage = ImageBuffer::new(w, h);
     let intensity: Interval = Interval{min: 0., max: 0.999};
-    let px_idx: u32 = 0;
-    for (i, j, pixel) in img.enumerate_pixels_mut() {
-        let v: Vec3 = img_matrix[j as usize][i as usize];
-        let r: u8 = (256.*intensity.clamp(v.0).sqrt()) as u8;
-        let g: u8 = (256.*intensity.clamp(v.1).sqrt()) as u8;
-        let b: u8 = (256.*intensity.clamp(v.2).sqrt()) as u8;
+    for (i, j, pixel) in png.enumerate_pixels_mut() {
+        let v: Vec3 = img[(j*w + i) as usize];
+        let r: u8 = (256.*encode(intensity.clamp(v.0))) as u8;
+        let g: u8 = (256.*encode(intensity.clamp(v.1))) as u8;
+        let b: u8 = (256.*encode(intensity.clamp(v.2))) as u8;
         *pixel = Rgb([r, g, b]);
     }
     let root: PathBuf = current_dir().unwrap();
     let full_path: PathBuf = root.join(path);
-    img.save(&full_path);
-}
\ No newline at end of file
+    png.save(&full_path);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format { // Image file formats, all but Png keep linear float radiance
+    Png,
+    Exr,
+    Hdr,
+    Pfm
+}
+
+pub const FORMATS: [&str; 4] = ["png", "exr", "hdr", "pfm"];
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "exr" => Some(Format::Exr),
+            "hdr" => Some(Format::Hdr),
+            "pfm" => Some(Format::Pfm),
+            _ => None
+        }
+    }
+
+    pub fn from_path(path: &str) -> Format { // By extension, 8-bit through the image crate for anything unknown
+        Path::new(path).extension().and_then(|e| e.to_str()).and_then(Format::from_name).unwrap_or(Format::Png)
+    }
+}
+
+pub struct Layer { // Named set of float channels sharing the image size, one EXR layer or one HDR/PFM file
+    pub name: String,
+    pub channels: Vec<(String, Vec<f32>)>
+}
+
+impl Layer {
+    pub fn rgb(name: &str, img: &[Vec3]) -> Layer {
+        Layer {
+            name: name.to_string(),
+            channels: vec![
+                (String::from("R"), img.iter().map(|c| c.0 as f32).collect()),
+                (String::from("G"), img.iter().map(|c| c.1 as f32).collect()),
+                (String::from("B"), img.iter().map(|c| c.2 as f32).collect())
+            ]
+        }
+    }
+
+    pub fn gray(name: &str, channel: &str, values: Vec<f32>) -> Layer {
+        Layer {name: name.to_string(), channels: vec![(channel.to_string(), values)]}
+    }
+
+    pub fn to_rgb(&self) -> Vec<Vec3> { // Single channel layers are repeated on all three, for HDR which has no grayscale
+        let c = |k: usize| &self.channels[k.min(self.channels.len() - 1)].1;
+        (0..c(0).len()).map(|i| Vec3(c(0)[i] as f64, c(1)[i] as f64, c(2)[i] as f64)).collect()
+    }
+}
+
+pub fn write_exr(path: &str, w: u32, h: u32, layers: &[Layer]) -> Result<(), exr::error::Error> { // All layers in one multi-part file
+    let parts: Vec<ExrLayer<AnyChannels<FlatSamples>>> = layers.iter().map(|l| {
+        let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = l.channels.iter()
+            .map(|(name, values)| AnyChannel::new(name.as_str(), FlatSamples::F32(values.clone())))
+            .collect();
+        ExrLayer::new((w as usize, h as usize), LayerAttributes::named(l.name.as_str()), Encoding::FAST_LOSSLESS, AnyChannels::sort(channels))
+    }).collect();
+    Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions((w as usize, h as usize))), parts).write().to_file(path)
+}
+
+pub fn write_hdr(path: &str, w: u32, h: u32, img: &[Vec3]) -> Result<(), image::ImageError> { // Radiance RGBE
+    let data: Vec<Rgb<f32>> = img.iter().map(|c| Rgb([c.0 as f32, c.1 as f32, c.2 as f32].map(|x| if (x.is_finite()) {x.max(0.)} else {0.}))).collect();
+    HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(&data, w as usize, h as usize)
+}
+
+pub fn write_pfm(path: &str, w: u32, h: u32, layer: &Layer) -> io::Result<()> { // Portable Float Map, little-endian with rows stored bottom to top, grayscale for single channel layers
+    let channels: Vec<&[f32]> = layer.channels.iter().take(3).map(|(_, values)| values.as_slice()).collect();
+    let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+    write!(writer, "{}\n{} {}\n-1.0\n", if (channels.len() == 1) {"Pf"} else {"PF"}, w, h)?;
+    for j in (0..h).rev() {
+        for i in 0..w {
+            for c in &channels {
+                writer.write_all(&c[(j*w + i) as usize].to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 { // sRGB transfer function (OETF)
+    if (c <= 0.0031308) {
+        12.92*c
+    } else {
+        1.055*c.powf(1./2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 { // Inverse of the sRGB transfer function
+    if (c <= 0.04045) {
+        c/12.92
+    } else {
+        ((c + 0.055)/1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn pfm_channels_follow_the_layer() {
+        let path: String = env::temp_dir().join(format!("raytracer-test-{}.pfm", std::process::id())).to_string_lossy().into_owned();
+        write_pfm(&path, 2, 1, &Layer::gray("depth", "Z", vec![1., 2.])).unwrap();
+        let gray: Vec<u8> = fs::read(&path).unwrap();
+        write_pfm(&path, 2, 1, &Layer::rgb("beauty", &[Vec3(1., 2., 3.), Vec3(4., 5., 6.)])).unwrap();
+        let rgb: Vec<u8> = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(gray.starts_with(b"Pf\n2 1\n-1.0\n") && gray.len() == 12 + 2*4);
+        assert!(rgb.starts_with(b"PF\n2 1\n-1.0\n") && rgb.len() == 12 + 6*4);
+        assert_eq!(gray[12..16], 1f32.to_le_bytes());
+    }
+}
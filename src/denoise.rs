@@ -0,0 +1,125 @@
+use crate::vec3::Vec3;
+use crate::aov::Aovs;
+
+const KERNEL: [f64; 5] = [1./16., 1./4., 3./8., 1./4., 1./16.]; // B3 spline, separable weights of the 5x5 a-trous taps
+
+#[derive(Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: u32,    // Passes of the filter, each doubling the spacing between taps
+    pub sigma_color: f64,   // Tolerance to color differences, halved every pass
+    pub sigma_albedo: f64,  // Tolerance to albedo differences
+    pub normal_power: f64   // Sharpness of the falloff with the angle between normals
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {iterations: 5, sigma_color: 0.5, sigma_albedo: 0.1, normal_power: 64.}
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, w: u32, h: u32, img: &[Vec3], aovs: &[Aovs]) -> Vec<Vec3> { // Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010) guided by albedo and normals
+        let (w, h): (i64, i64) = (w as i64, h as i64);
+        let albedo: Vec<Vec3> = aovs.iter().map(|a| Vec3(a.albedo.0.max(0.01), a.albedo.1.max(0.01), a.albedo.2.max(0.01))).collect();
+        let mut irradiance: Vec<Vec3> = despeckle(w, h, img).iter().zip(albedo.iter()) // Filtering light rather than color keeps textures sharp
+            .map(|(c, a)| Vec3(c.0/a.0, c.1/a.1, c.2/a.2))
+            .collect();
+
+        let mut sigma_color: f64 = self.sigma_color;
+        for it in 0..self.iterations {
+            let step: i64 = 1 << it;
+            let mut next: Vec<Vec3> = Vec::with_capacity(irradiance.len());
+            for j in 0..h {
+                for i in 0..w {
+                    let p: usize = (j*w + i) as usize;
+                    let cp: Vec3 = compress(irradiance[p]);
+                    let mut sum: Vec3 = Vec3(0., 0., 0.);
+                    let mut weight_sum: f64 = 0.;
+                    for (dj, kj) in KERNEL.iter().enumerate() {
+                        for (di, ki) in KERNEL.iter().enumerate() {
+                            let qi: i64 = i + (di as i64 - 2)*step;
+                            let qj: i64 = j + (dj as i64 - 2)*step;
+                            if (qi < 0 || qi >= w || qj < 0 || qj >= h) {
+                                continue;
+                            }
+                            let q: usize = (qj*w + qi) as usize;
+                            let weight: f64 = ki*kj*self.edge_weight(&aovs[p], &aovs[q], cp, compress(irradiance[q]), sigma_color);
+                            sum += weight*irradiance[q];
+                            weight_sum += weight;
+                        }
+                    }
+                    next.push(if (weight_sum > 0.) {sum/weight_sum} else {irradiance[p]});
+                }
+            }
+            irradiance = next;
+            sigma_color *= 0.5;
+        }
+
+        irradiance.iter().zip(albedo.iter()).map(|(e, a)| e.coord_mul(*a)).collect()
+    }
+
+    fn edge_weight(&self, p: &Aovs, q: &Aovs, cp: Vec3, cq: Vec3, sigma_color: f64) -> f64 { // How much pixel q may contribute to p, from 0 across edges to 1 on smooth regions
+        if (p.object.is_some() != q.object.is_some()) {
+            return 0.;                                                              // Never blend the background into geometry
+        }
+        let w_normal: f64 = if (p.object.is_some()) {(p.normal*q.normal).max(0.).powf(self.normal_power)} else {1.};
+        let w_albedo: f64 = (-(p.albedo - q.albedo).l2norm()/(self.sigma_albedo*self.sigma_albedo)).exp();
+        let w_color: f64 = (-(cp - cq).l2norm()/(sigma_color*sigma_color)).exp();
+        w_normal*w_albedo*w_color
+    }
+}
+
+fn despeckle(w: i64, h: i64, img: &[Vec3]) -> Vec<Vec3> { // Clamps every pixel to the brightest of its 8 neighbors, since the edge stops would otherwise keep lone fireflies intact
+    let mut out: Vec<Vec3> = Vec::with_capacity(img.len());
+    for j in 0..h {
+        for i in 0..w {
+            let mut hi: Vec3 = Vec3(0., 0., 0.);
+            for (di, dj) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (qi, qj): (i64, i64) = (i + di, j + dj);
+                if (qi >= 0 && qi < w && qj >= 0 && qj < h) {
+                    let q: Vec3 = img[(qj*w + qi) as usize];
+                    hi = Vec3(hi.0.max(q.0), hi.1.max(q.1), hi.2.max(q.2));
+                }
+            }
+            let p: Vec3 = img[(j*w + i) as usize];
+            out.push(Vec3(p.0.min(hi.0), p.1.min(hi.1), p.2.min(hi.2)));
+        }
+    }
+    out
+}
+
+fn compress(c: Vec3) -> Vec3 { // Maps radiance into [0, 1) so color distances aren't dominated by fireflies
+    Vec3(c.0/(1. + c.0), c.1/(1. + c.1), c.2/(1. + c.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(albedo: f64) -> Aovs {
+        Aovs {depth: 1., normal: Vec3(0., 0., 1.), albedo: Vec3(albedo, albedo, albedo), position: Vec3(0., 0., 0.), object: Some(0)}
+    }
+
+    #[test]
+    fn flat_image_stays_flat() {
+        let img: Vec<Vec3> = vec![Vec3(0.3, 0.6, 0.9); 64];
+        let out: Vec<Vec3> = Denoiser::default().denoise(8, 8, &img, &vec![hit(0.5); 64]);
+        assert!(out.iter().all(|c| (*c - Vec3(0.3, 0.6, 0.9)).len() < 1e-9));
+    }
+
+    #[test]
+    fn keeps_geometry_and_background_apart() {
+        let aovs: Vec<Aovs> = (0..64).map(|p| if (p % 8 < 4) {hit(0.5)} else {Aovs::miss()}).collect(); // Left half is a surface, right half the sky
+        let img: Vec<Vec3> = (0..64).map(|p| if (p % 8 < 4) {Vec3(0.2, 0.2, 0.2)} else {Vec3(1., 1., 1.)}).collect();
+        let out: Vec<Vec3> = Denoiser::default().denoise(8, 8, &img, &aovs);
+        assert!(out.iter().zip(&img).all(|(a, b)| (*a - *b).len() < 1e-9));
+    }
+
+    #[test]
+    fn smooths_noise() {
+        let img: Vec<Vec3> = (0..256).map(|p| if (p % 3 == 0) {Vec3(0.8, 0.8, 0.8)} else {Vec3(0.4, 0.4, 0.4)}).collect();
+        let spread = |img: &[Vec3]| img.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max) - img.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+        let out: Vec<Vec3> = Denoiser::default().denoise(16, 16, &img, &vec![hit(0.5); 256]);
+        assert!(spread(&out) < 0.5*spread(&img), "spread {} from {}", spread(&out), spread(&img));
+    }
+}
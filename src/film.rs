@@ -0,0 +1,139 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PixelFilter { // Weight of a sample for a pixel, by their distance, separable in x and y
+    Box,      // Flat, each pixel averages the samples within radius of its center
+    Tent,     // Linear falloff to 0 at radius
+    Gaussian, // Standard deviation radius/3, shifted down to reach 0 at radius
+    Mitchell, // Mitchell-Netravali cubic with B = C = 1/3, stretched over radius
+    Lanczos   // Windowed sinc with as many lobes as the radius in pixels
+}
+
+pub const NAMES: [&str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+pub const MIN_RADIUS: f64 = 0.5; // In pixels, below it samples near the pixel edges would reach no pixel center
+
+impl PixelFilter {
+    pub fn from_name(name: &str) -> Option<PixelFilter> {
+        match name {
+            "box" => Some(PixelFilter::Box),
+            "tent" => Some(PixelFilter::Tent),
+            "gaussian" => Some(PixelFilter::Gaussian),
+            "mitchell" => Some(PixelFilter::Mitchell),
+            "lanczos" => Some(PixelFilter::Lanczos),
+            _ => None
+        }
+    }
+
+    pub fn default_radius(self) -> f64 { // In pixels, 0.5 for box keeps every sample within its own pixel
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.,
+            PixelFilter::Gaussian => 1.5,
+            PixelFilter::Mitchell => 2.,
+            PixelFilter::Lanczos => 3.
+        }
+    }
+
+    pub fn weight(self, dx: f64, dy: f64, radius: f64) -> f64 { // dx, dy from the pixel center to the sample, in pixels
+        self.weight_1d(dx, radius)*self.weight_1d(dy, radius)
+    }
+
+    fn weight_1d(self, x: f64, radius: f64) -> f64 {
+        let x: f64 = x.abs();
+        if (x >= radius) {
+            return 0.;
+        }
+        match self {
+            PixelFilter::Box => 1.,
+            PixelFilter::Tent => radius - x,
+            PixelFilter::Gaussian => {
+                let sigma: f64 = radius/3.;
+                let gaussian = |x: f64| (-x*x/(2.*sigma*sigma)).exp();
+                gaussian(x) - gaussian(radius)
+            },
+            PixelFilter::Mitchell => mitchell(2.*x/radius),
+            PixelFilter::Lanczos => sinc(x)*sinc(x/radius)
+        }
+    }
+}
+
+fn mitchell(x: f64) -> f64 { // Cubic over [0, 2] with B = C = 1/3
+    let (b, c): (f64, f64) = (1./3., 1./3.);
+    if (x < 1.) {
+        ((12. - 9.*b - 6.*c)*x*x*x + (-18. + 12.*b + 6.*c)*x*x + (6. - 2.*b))/6.
+    } else {
+        ((-b - 6.*c)*x*x*x + (6.*b + 30.*c)*x*x + (-12.*b - 48.*c)*x + (8.*b + 24.*c))/6.
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if (x < 1e-5) {
+        return 1.;
+    }
+    (PI*x).sin()/(PI*x)
+}
+
+#[derive(Clone)]
+pub struct Film { // Filter-weighted sums of samples over a rectangle of pixels, which may reach past the image
+    pub x0: i64,          // Upper-left pixel of the rectangle
+    pub y0: i64,
+    pub w: u32,
+    pub h: u32,
+    pub sum: Vec<Vec3>,   // Row-major weighted radiance
+    pub weight: Vec<f64>  // Row-major sum of weights
+}
+
+impl Film {
+    pub fn new(x0: i64, y0: i64, w: u32, h: u32) -> Film {
+        Film {x0, y0, w, h, sum: vec![Vec3(0., 0., 0.); (w*h) as usize], weight: vec![0.; (w*h) as usize]}
+    }
+
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Vec3, filter: PixelFilter, radius: f64) { // Splats a sample at image point (x, y) into every pixel of the rectangle whose center is within radius
+        let px0: i64 = ((x - 0.5 - radius).ceil() as i64).max(self.x0);
+        let py0: i64 = ((y - 0.5 - radius).ceil() as i64).max(self.y0);
+        let px1: i64 = ((x - 0.5 + radius).floor() as i64).min(self.x0 + self.w as i64 - 1);
+        let py1: i64 = ((y - 0.5 + radius).floor() as i64).min(self.y0 + self.h as i64 - 1);
+        for py in py0..=py1 {
+            for px in px0..=px1 {
+                let weight: f64 = filter.weight(x - (px as f64 + 0.5), y - (py as f64 + 0.5), radius);
+                if (weight == 0.) {
+                    continue;
+                }
+                let k: usize = ((py - self.y0)*self.w as i64 + (px - self.x0)) as usize;
+                self.sum[k] += weight*color;
+                self.weight[k] += weight;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Film) { // Adds the overlapping part of other, pixels outside this film are dropped
+        for oy in 0..other.h as i64 {
+            let y: i64 = other.y0 + oy - self.y0;
+            if (y < 0 || y >= self.h as i64) {
+                continue;
+            }
+            for ox in 0..other.w as i64 {
+                let x: i64 = other.x0 + ox - self.x0;
+                if (x < 0 || x >= self.w as i64) {
+                    continue;
+                }
+                let (k, ok): (usize, usize) = ((y*self.w as i64 + x) as usize, (oy*other.w as i64 + ox) as usize);
+                self.sum[k] += other.sum[ok];
+                self.weight[k] += other.weight[ok];
+            }
+        }
+    }
+
+    pub fn resolve(&self) -> Vec<Vec3> { // Weighted average per pixel, black where nothing landed
+        self.sum.iter().zip(self.weight.iter()).map(|(s, w)| {
+            if (*w <= 0.) {
+                return Vec3(0., 0., 0.);
+            }
+            let c: Vec3 = *s/(*w);
+            Vec3(c.0.max(0.), c.1.max(0.), c.2.max(0.))                             // Negative lobes can ring below zero next to bright edges
+        }).collect()
+    }
+}
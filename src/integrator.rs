@@ -0,0 +1,123 @@
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::surface::{HitRecord, Hittable, Sample};
+use crate::interval::Interval;
+use crate::world::World;
+use crate::materials::{Scatter, ScatterRecord};
+use crate::sampler::Sampler;
+use crate::aov::Aovs;
+
+pub struct Integrator {
+    pub max_depth: Option<u32>, // Optional hard cap on the number of bounces
+    pub rr_depth: u32           // Bounces before Russian roulette may terminate a path
+}
+
+impl Integrator {
+    pub fn ray_color(&self, r: Ray, world: &World, aovs: &mut Aovs, sampler: &mut Sampler) -> Vec3 { // Also fills aovs with the first hit, except for its depth which the camera knows how to measure
+        let mut color: Vec3 = Vec3(0., 0., 0.);
+        let mut throughput: Vec3 = Vec3(1., 1., 1.);                                // Product of all attenuations along the path so far
+        let mut r: Ray = r;
+        let mut depth: u32 = 0;
+        let mut specular_bounce: bool = true;                                       // Whether the last direction was chosen without light sampling
+        let mut bsdf_pdf: f64 = 0.;                                                 // Density of the last scattered direction
+        loop {
+            if (self.max_depth.is_some_and(|max_d| depth >= max_d)) {
+                break;
+            }
+
+            let mut rec: HitRecord = HitRecord::new_empty();
+            if (!world.hit(r, Interval{min: 0.000001, max: f64::INFINITY}, &mut rec)) {
+                if (depth == 0) {
+                    aovs.albedo = world.background.color(r);
+                }
+                color += throughput.coord_mul(world.background.color(r));
+                break;
+            }
+            if (depth == 0) {
+                *aovs = Aovs {depth: f64::INFINITY, normal: rec.n, albedo: rec.mat.albedo(&rec), position: rec.p, object: Some(rec.id)};
+            }
+
+            let emitted: Vec3 = rec.mat.emitted(r, &rec);
+            if (!emitted.near_zero()) {
+                if (specular_bounce) {
+                    color += throughput.coord_mul(emitted);
+                } else {                                                            // Also reachable by the light sample taken at the previous hit
+                    let light_pdf: f64 = world.surfaces[rec.id].pdf(r.ori, r.dir, r.time)/world.lights.len() as f64;
+                    color += power_heuristic(bsdf_pdf, light_pdf)*throughput.coord_mul(emitted);
+                }
+            }
+
+            let light_uc: f64 = sampler.get_1d();                                   // Every bounce draws the same dimensions whichever branch it takes, so they line up across samples
+            let light_u: (f64, f64) = sampler.get_2d();
+            let bsdf_uc: f64 = sampler.get_1d();
+            let bsdf_u: (f64, f64) = sampler.get_2d();
+            let rr_u: f64 = sampler.get_1d();
+
+            let (r_out, attenuation): (Ray, Vec3) = match rec.mat.scatter(r, &rec, bsdf_uc, bsdf_u) {
+                None => break,
+                Some(ScatterRecord::Specular {ray, attenuation}) => {
+                    specular_bounce = true;
+                    (ray, attenuation)
+                },
+                Some(ScatterRecord::Sampled {ray, pdf}) => {
+                    if (pdf <= 0.) {
+                        break;
+                    }
+                    specular_bounce = false;
+                    bsdf_pdf = pdf;
+                    color += throughput.coord_mul(Self::sample_light(world, r, &rec, light_uc, light_u));
+                    (ray, rec.mat.eval(&rec, ray.dir, -r.dir.unit())/pdf)
+                }
+            };
+            if (attenuation.near_zero()) {
+                break;
+            }
+            throughput = throughput.coord_mul(attenuation);
+            depth += 1;
+
+            if (depth >= self.rr_depth) {
+                let p_survive: f64 = throughput.max_coord().min(0.95);              // Dim paths are likely to be cut, bright ones rarely
+                if (rr_u >= p_survive) {
+                    break;
+                }
+                throughput = throughput/p_survive;                                  // Reweight survivors so the estimate stays unbiased
+            }
+            r = r_out;
+        }
+        color
+    }
+
+    fn sample_light(world: &World, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Vec3 { // MIS-weighted radiance reaching rec from a randomly chosen emitter
+        if (world.lights.is_empty()) {
+            return Vec3(0., 0., 0.);
+        }
+        let n_lights: usize = world.lights.len();
+        let light_id: usize = world.lights[((uc*n_lights as f64) as usize).min(n_lights - 1)];
+        let light = &world.surfaces[light_id];
+        let dir: Vec3 = light.sample(rec.p, r_in.time, u);
+        let light_pdf: f64 = light.pdf(rec.p, dir, r_in.time)/n_lights as f64;
+        let f: Vec3 = rec.mat.eval(rec, dir, -r_in.dir.unit());
+        if (light_pdf <= 0. || f.near_zero()) {
+            return Vec3(0., 0., 0.);
+        }
+
+        let shadow_ray: Ray = Ray {ori: rec.p, dir, time: r_in.time};
+        let mut light_rec: HitRecord = HitRecord::new_empty();
+        if (!world.hit(shadow_ray, Interval{min: 0.000001, max: f64::INFINITY}, &mut light_rec) || light_rec.id != light_id) {
+            return Vec3(0., 0., 0.);                                                // Occluded
+        }
+        let emitted: Vec3 = light_rec.mat.emitted(shadow_ray, &light_rec);
+        let weight: f64 = power_heuristic(light_pdf, rec.mat.pdf(rec, dir));
+        (weight/light_pdf)*f.coord_mul(emitted)
+    }
+}
+
+fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 { // MIS weight of a sample drawn with density pdf_a against a competing strategy with density pdf_b
+    let a2: f64 = pdf_a*pdf_a;
+    let b2: f64 = pdf_b*pdf_b;
+    if (a2 + b2 <= 0.) {
+        0.
+    } else {
+        a2/(a2 + b2)
+    }
+}
@@ -5,6 +5,18 @@ pub struct Interval {
 }
 
 impl Interval {
+    pub fn merge(a: Interval, b: Interval) -> Interval { // Smallest interval enclosing both
+        Interval {min: a.min.min(b.min), max: a.max.max(b.max)}
+    }
+
+    pub fn size(self) -> f64 {
+        self.max - self.min
+    }
+
+    pub fn expand(self, delta: f64) -> Interval { // Grows by delta/2 on each side
+        Interval {min: self.min - delta/2., max: self.max + delta/2.}
+    }
+
     pub fn contains(self, x: f64) -> bool {
         self.min <= x && x <= self.max
     }
@@ -16,7 +28,7 @@ impl Interval {
     pub fn clamp(self, x: f64) -> f64 {
         if (x < self.min) {
             self.min
-        } else if {x > self.max} {
+        } else if (x > self.max) {
             self.max
         } else {
             x
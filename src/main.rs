@@ -3,82 +3,87 @@
 #![allow(dead_code)]
 
 use std::env;
+use std::path::Path;
+use std::process;
 
 pub mod vec3;
 pub mod ray;
 pub mod color;
 pub mod surface;
 pub mod sphere;
+pub mod triangle;
 pub mod interval;
 pub mod world;
 pub mod camera;
 pub mod random;
 pub mod materials;
+pub mod texture;
+pub mod noise;
+pub mod obj;
+pub mod integrator;
+pub mod onb;
+pub mod aabb;
+pub mod bvh;
+pub mod scene;
+pub mod cli;
+pub mod aov;
+pub mod denoise;
+pub mod sampler;
+pub mod film;
+pub mod adaptive;
+pub mod checkpoint;
+pub mod tonemap;
 
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::surface::{HitRecord, Hittable};
-use crate::sphere::Sphere;
 use crate::interval::Interval;
-use crate::world::{World, Surfaces};
-use crate::camera::Camera;
-use crate::random::{random, random_in};
-use crate::materials::{Materials, Lambertian, Metal, Dielectric};
+use crate::camera::{Camera, CameraBuilder, CameraError};
+use crate::scene::{Scene, SceneError, load_scene, builtin, BUILTINS};
+use crate::cli::{Command, RenderArgs, CliError, USAGE};
+use crate::checkpoint::Checkpoint;
 
-fn main() { // cargo run --release -- out/out.png
-    let args: Vec<String> = env::args().collect();
-    let output_file: String = args[1].clone();
-
-    let mut world: World = World {surfaces: vec![]};
-    let ground_material: Materials = Materials::Lambertian(Lambertian {color: Vec3(0.5, 0.5, 0.5)});
-    let material1: Materials = Materials::Dielectric(Dielectric {color: Vec3(1., 1., 1.), refraction_idx: 1.5});
-    let material2: Materials = Materials::Lambertian(Lambertian {color: Vec3(0.4, 0.2, 0.1)});
-    let material3: Materials = Materials::Metal(Metal {color: Vec3(0.7, 0.6, 0.5), fuzz: 0.});
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat: f64 = random();
-            let center: Vec3 = Vec3(a as f64 + 0.9*random(), 0.2, b as f64 + 0.9*random());
-            if ((center - Vec3(4., 0.2, 0.)).len() > 0.9) {
-                let mut sphere_material: Materials;
-                if (choose_mat < 0.8) {
-                    // diffuse
-                    let albedo: Vec3 = Vec3::random().coord_mul(Vec3::random());
-                    sphere_material = Materials::Lambertian(Lambertian {color: albedo});
-                    world.surfaces.push(Surfaces::Sphere(Sphere {center: center, radius: 0.2, mat: sphere_material}));
-                } else if (choose_mat < 0.95) {
-                    // metal
-                    let albedo: Vec3 = Vec3::random_in(0.5, 1.);
-                    let fuzz: f64 = random_in(0., 0.5);
-                    sphere_material = Materials::Metal(Metal {color: albedo, fuzz: fuzz});
-                    world.surfaces.push(Surfaces::Sphere(Sphere {center: center, radius: 0.2, mat: sphere_material}));
-                } else {
-                    // glass
-                    sphere_material = Materials::Dielectric(Dielectric {color: Vec3(1., 1., 1.), refraction_idx: 1.5});
-                    world.surfaces.push(Surfaces::Sphere(Sphere {center: center, radius: 0.2, mat: sphere_material}));
-                }
+fn main() { // cargo run --release -- scenes/cornell.toml -o out/out.png
+    let args: Vec<String> = env::args().skip(1).collect();
+    let render_args: RenderArgs = match cli::parse(&args) {
+        Ok(Command::Render(render_args)) => *render_args,
+        Ok(Command::ListScenes) => {
+            for name in BUILTINS.iter() {
+                println!("{}", name);
             }
-        }
-    }
+            return;
+        },
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => usage_error(e)
+    };
 
-    world.surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(0., -1000., 0.), radius: 1000., mat: ground_material}));
-    world.surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(0., 1., 0.), radius: 1., mat: material1}));
-    world.surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(-4., 1., 0.), radius: 1., mat: material2}));
-    world.surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(4., 1., 0.), radius: 1., mat: material3}));
+    let scene: Scene = match builtin(&render_args.scene, render_args.seed) {
+        Some(scene) => scene,
+        None => load_scene(Path::new(&render_args.scene), render_args.seed).unwrap_or_else(|e| fail(e))
+    };
+    let mut builder: CameraBuilder = render_args.apply(scene.camera);
+    if let Some(path) = &render_args.resume {
+        builder = builder.resume(Checkpoint::read(path).unwrap_or_else(|e| fail(e)));
+    }
+    let camera: Camera = match builder.build() {
+        Ok(camera) => camera,
+        Err(e @ CameraError::Resume(_)) => fail(e),
+        Err(CameraError::NoOutputFile) => usage_error(CliError(String::from("no output file, pass --output or set render.output in the scene"))),
+        Err(e) => usage_error(CliError(e.to_string()))
+    };
+    camera.render(scene.world);
+}
 
-    let camera: Camera = Camera::new(
-        output_file,
-        Vec3(0., 0., 0.),
-        Vec3(13., 2., 3.), 
-        Vec3(0., 1., 0.),
-        0.65,
-        10.,
-        16.0/9.0,
-        2000,
-        50,
-        20.,
-        64
-    );
+fn usage_error(e: CliError) -> ! {
+    eprintln!("error: {}", e);
+    eprintln!("Run 'raytracer --help' for usage.");
+    process::exit(2);
+}
 
-    camera.render(world);
+fn fail(e: impl std::error::Error) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1);
 }
\ No newline at end of file
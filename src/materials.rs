@@ -1,75 +1,171 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::surface::HitRecord;
-use crate::random::random;
+use crate::onb::Onb;
+use crate::texture::{Texture, Textures};
+use std::f64::consts::PI;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum Materials { // Innecesario, no necesito armar vector de materiales... considerar reemplazar por trait donde usado
     Lambertian(Lambertian),
     Metal(Metal),
-    Dielectric(Dielectric)
+    Dielectric(Dielectric),
+    DiffuseLight(DiffuseLight)
+}
+
+pub enum ScatterRecord {
+    Specular {ray: Ray, attenuation: Vec3}, // Delta-like lobe, followed as is and invisible to light sampling
+    Sampled {ray: Ray, pdf: f64}            // Direction drawn with solid angle density pdf, weighted by eval/pdf
 }
 
 pub trait Scatter {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> (Ray, Vec3); // Scattered ray, color (attenuations)
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord>; // None if the ray is absorbed, uc and u are the sample values picking the lobe and the direction
+
+    fn emitted(&self, r_in: Ray, rec: &HitRecord) -> Vec3 { // Radiance emitted from the hit point towards the incoming ray
+        Vec3(0., 0., 0.)
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3) -> f64 { // Solid angle density of scatter choosing direction wi
+        0.
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Vec3 { // BSDF times cosine term for light arriving from wi and leaving towards wo
+        Vec3(0., 0., 0.)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 { // Base color at the hit point, as seen by the albedo AOV
+        Vec3(0., 0., 0.)
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Lambertian {
-    pub color: Vec3
+    pub color: Textures
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Metal {
-    pub color: Vec3,
+    pub color: Textures,
     pub fuzz: f64
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Dielectric {
-    pub color: Vec3,
+    pub color: Textures,
     pub refraction_idx: f64
 }
 
+#[derive(Clone)]
+pub struct DiffuseLight {
+    pub color: Textures,
+    pub intensity: f64
+}
+
 impl Scatter for Lambertian {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> (Ray, Vec3) {
-        let mut scattered_dir: Vec3 = rec.n + Vec3::random_unit();
-        if (scattered_dir.near_zero()) {
-            scattered_dir = rec.n;
-        }
-        (Ray {ori: rec.p, dir: scattered_dir}, self.color)
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord> {
+        let scattered_dir: Vec3 = Onb::new(rec.n).local(Vec3::cosine_direction(u));
+        Some(ScatterRecord::Sampled {ray: Ray {ori: rec.p, dir: scattered_dir, time: r_in.time}, pdf: self.pdf(rec, scattered_dir)})
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3) -> f64 {
+        (rec.n*wi.unit()).max(0.)/PI
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Vec3 {
+        ((rec.n*wi.unit()).max(0.)/PI)*self.color.value(rec.u, rec.v, rec.p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        self.color.value(rec.u, rec.v, rec.p)
     }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> (Ray, Vec3) {              // Scattered ray, color, reflectance (attenuation)
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord> {
         let reflected_dir: Vec3 = r_in.dir.unit().reflect(rec.n);
-        let scattered_dir: Vec3 = reflected_dir + self.fuzz*Vec3::random_unit();
-        (Ray {ori: rec.p, dir: scattered_dir}, self.color)
+        let scattered_dir: Vec3 = reflected_dir + self.fuzz*Vec3::uniform_sphere(u);
+        Some(ScatterRecord::Specular {ray: Ray {ori: rec.p, dir: scattered_dir, time: r_in.time}, attenuation: self.color.value(rec.u, rec.v, rec.p)})
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        self.color.value(rec.u, rec.v, rec.p)
     }
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> (Ray, Vec3) {              // Scattered ray, color, reflectance (attenuation)
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord> {
         let idx_ratio: f64 = if (rec.front) {1./self.refraction_idx} else {self.refraction_idx};
         let unit_dir: Vec3 = r_in.dir.unit();
         let cos_theta: f64 = f64::min(-unit_dir*rec.n, 1.);
         let sin_theta: f64 = (1. - cos_theta*cos_theta).sqrt();
         let cannot_refract: bool = idx_ratio*sin_theta > 1.;
-        if (cannot_refract || Self::reflectance(cos_theta, idx_ratio) > random()) {
-            (Ray {ori: rec.p, dir: unit_dir.reflect(rec.n)}, Vec3(1., 1., 1.))
+        if (cannot_refract || Self::reflectance(cos_theta, idx_ratio) > uc) {
+            Some(ScatterRecord::Specular {ray: Ray {ori: rec.p, dir: unit_dir.reflect(rec.n), time: r_in.time}, attenuation: Vec3(1., 1., 1.)})
+        } else {
+            Some(ScatterRecord::Specular {ray: Ray {ori: rec.p, dir: unit_dir.refract(rec.n, idx_ratio), time: r_in.time}, attenuation: self.color.value(rec.u, rec.v, rec.p)})
+        }
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        self.color.value(rec.u, rec.v, rec.p)
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, r_in: Ray, rec: &HitRecord) -> Vec3 {                    // Only the outer side of a surface glows
+        if (rec.front) {
+            self.intensity*self.color.value(rec.u, rec.v, rec.p)
         } else {
-            (Ray {ori: rec.p, dir: unit_dir.refract(rec.n, idx_ratio)}, self.color)
+            Vec3(0., 0., 0.)
         }
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        self.color.value(rec.u, rec.v, rec.p)
+    }
 }
 
 impl Scatter for Materials {
-    fn scatter(&self, r_in: Ray, rec: &HitRecord) -> (Ray, Vec3) {
+    fn scatter(&self, r_in: Ray, rec: &HitRecord, uc: f64, u: (f64, f64)) -> Option<ScatterRecord> {
+        match self {
+            Materials::Lambertian(l) => l.scatter(r_in, rec, uc, u),
+            Materials::Metal(m) => m.scatter(r_in, rec, uc, u),
+            Materials::Dielectric(d) => d.scatter(r_in, rec, uc, u),
+            Materials::DiffuseLight(l) => l.scatter(r_in, rec, uc, u)
+        }
+    }
+
+    fn emitted(&self, r_in: Ray, rec: &HitRecord) -> Vec3 {
+        match self {
+            Materials::DiffuseLight(l) => l.emitted(r_in, rec),
+            _ => Vec3(0., 0., 0.)
+        }
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3) -> f64 {
+        match self {
+            Materials::Lambertian(l) => l.pdf(rec, wi),
+            _ => 0.
+        }
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Vec3 {
+        match self {
+            Materials::Lambertian(l) => l.eval(rec, wi, wo),
+            _ => Vec3(0., 0., 0.)
+        }
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
         match self {
-            Materials::Lambertian(l) => l.scatter(r_in, rec),
-            Materials::Metal(m) => m.scatter(r_in, rec),
-            Materials::Dielectric(d) => d.scatter(r_in, rec)
+            Materials::Lambertian(l) => l.albedo(rec),
+            Materials::Metal(m) => m.albedo(rec),
+            Materials::Dielectric(d) => d.albedo(rec),
+            Materials::DiffuseLight(l) => l.albedo(rec)
         }
     }
 }
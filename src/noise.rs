@@ -0,0 +1,116 @@
+use crate::vec3::Vec3;
+use fastrand::Rng;
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin { // Gradient noise over a lattice of random unit vectors
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin { // Draws from its own generator, so the lattice depends on seed alone and the thread's stream is left alone
+        let mut rng: Rng = Rng::with_seed(seed);
+        let gradients: Vec<Vec3> = (0..POINT_COUNT).map(|_| Self::gradient(&mut rng)).collect();
+        Perlin {
+            gradients,
+            perm_x: Self::permutation(&mut rng),
+            perm_y: Self::permutation(&mut rng),
+            perm_z: Self::permutation(&mut rng)
+        }
+    }
+
+    fn gradient(rng: &mut Rng) -> Vec3 { // Random unit vector, by rejection from the unit ball
+        loop {
+            let v: Vec3 = Vec3(2.*rng.f64() - 1., 2.*rng.f64() - 1., 2.*rng.f64() - 1.);
+            let l2: f64 = v.l2norm();
+            if (l2 < 1. && l2 > 1e-12) {
+                return v.unit();
+            }
+        }
+    }
+
+    fn permutation(rng: &mut Rng) -> Vec<usize> { // Fisher-Yates shuffle of 0..POINT_COUNT
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            p.swap(i, rng.usize(..=i));
+        }
+        p
+    }
+
+    pub fn noise(&self, p: Vec3) -> f64 { // Smooth value in about [-1, 1], zero on lattice points
+        let (fx, fy, fz): (f64, f64, f64) = (p.0.floor(), p.1.floor(), p.2.floor());
+        let (u, v, w): (f64, f64, f64) = (p.0 - fx, p.1 - fy, p.2 - fz);
+        let (i, j, k): (i64, i64, i64) = (fx as i64, fy as i64, fz as i64);
+
+        let uu: f64 = u*u*(3. - 2.*u);                                              // Hermite smoothing hides the lattice
+        let vv: f64 = v*v*(3. - 2.*v);
+        let ww: f64 = w*w*(3. - 2.*w);
+        let mut acc: f64 = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let idx: usize = self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize];
+                    let weight: Vec3 = Vec3(u - di as f64, v - dj as f64, w - dk as f64);
+                    let (a, b, c): (f64, f64, f64) = (di as f64, dj as f64, dk as f64);
+                    acc += (a*uu + (1. - a)*(1. - uu))
+                        *(b*vv + (1. - b)*(1. - vv))
+                        *(c*ww + (1. - c)*(1. - ww))
+                        *(self.gradients[idx]*weight);
+                }
+            }
+        }
+        acc
+    }
+
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f64 { // Fractal sum of octaves, each at twice the frequency and half the amplitude of the last
+        let mut acc: f64 = 0.;
+        let mut q: Vec3 = p;
+        let mut amplitude: f64 = 1.;
+        for _ in 0..octaves {
+            acc += amplitude*self.noise(q);
+            amplitude *= 0.5;
+            q = 2.*q;
+        }
+        acc
+    }
+
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f64 { // Like fbm but summing absolute values, which creases the pattern
+        let mut acc: f64 = 0.;
+        let mut q: Vec3 = p;
+        let mut amplitude: f64 = 1.;
+        for _ in 0..octaves {
+            acc += amplitude*self.noise(q).abs();
+            amplitude *= 0.5;
+            q = 2.*q;
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{random, seed_rng};
+
+    #[test]
+    fn leaves_the_thread_generator_alone() {
+        seed_rng(3);
+        let expected: f64 = random();
+        seed_rng(3);
+        let _perlin: Perlin = Perlin::new(7);
+        assert_eq!(random(), expected);
+    }
+
+    #[test]
+    fn same_seed_same_lattice() {
+        let (a, b, c): (Perlin, Perlin, Perlin) = (Perlin::new(7), Perlin::new(7), Perlin::new(8));
+        let points: Vec<Vec3> = (0..100).map(|i| Vec3(0.37*i as f64, 0.11*i as f64, 0.53*i as f64)).collect();
+        assert!(points.iter().all(|&p| a.noise(p) == b.noise(p)));
+        assert!(points.iter().any(|&p| a.noise(p) != c.noise(p)));
+    }
+}
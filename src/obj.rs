@@ -0,0 +1,320 @@
+use std::fmt;
+use std::fs;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::vec3::Vec3;
+use crate::triangle::Triangle;
+use crate::world::Surfaces;
+use crate::materials::{Materials, Lambertian, Metal, Dielectric, DiffuseLight};
+use crate::texture::{Textures, ImageTexture};
+
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    pub groups: Vec<(String, Range<usize>)> // Group names with the triangles declared under them
+}
+
+impl Mesh {
+    pub fn transform(&mut self, scale: f64, offset: Vec3) { // Uniform scale about the origin followed by a translation
+        for triangle in self.triangles.iter_mut() {
+            for v in triangle.v.iter_mut() {
+                *v = scale*(*v) + offset;
+            }
+        }
+    }
+
+    pub fn into_surfaces(self) -> impl Iterator<Item = Surfaces> {
+        self.triangles.into_iter().map(Surfaces::Triangle)
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse {path: PathBuf, line: usize, msg: String}
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse {path, line, msg} => write!(f, "{}:{}: {}", path.display(), line, msg)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+type Corner = (Vec3, Option<(f64, f64)>, Option<Vec3>); // Position, texture coordinates and normal of a face vertex
+
+struct Parser<'a> { // Tracks where we are for error reporting
+    path: &'a Path,
+    line: usize
+}
+
+impl Parser<'_> {
+    fn err(&self, msg: String) -> ObjError {
+        ObjError::Parse {path: self.path.to_path_buf(), line: self.line, msg}
+    }
+
+    fn floats(&self, args: &[&str], min: usize, max: usize, what: &str) -> Result<Vec<f64>, ObjError> {
+        if (args.len() < min || args.len() > max) {
+            return Err(self.err(format!("{} expects {} to {} numbers, got {}", what, min, max, args.len())));
+        }
+        args.iter().map(|a| a.parse::<f64>().map_err(|_| self.err(format!("invalid number '{}' in {}", a, what)))).collect()
+    }
+
+    fn vec3(&self, args: &[&str], what: &str) -> Result<Vec3, ObjError> {
+        let xs: Vec<f64> = self.floats(args, 3, 3, what)?;
+        Ok(Vec3(xs[0], xs[1], xs[2]))
+    }
+
+    fn index(&self, s: &str, len: usize, what: &str) -> Result<usize, ObjError> { // 1-based, negative counts back from the last element
+        let i: i64 = s.parse().map_err(|_| self.err(format!("invalid {} index '{}'", what, s)))?;
+        let resolved: i64 = if (i < 0) {len as i64 + i} else {i - 1};
+        if (i == 0 || resolved < 0 || resolved >= len as i64) {
+            return Err(self.err(format!("{} index {} out of range (have {})", what, i, len)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+pub fn load_obj(path: &Path, default_mat: Materials) -> Result<Mesh, ObjError> { // Triangulated mesh with materials from the referenced .mtl libraries
+    let src: String = fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let dir: &Path = path.parent().unwrap_or(Path::new(""));
+    let mut p: Parser = Parser {path, line: 0};
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f64, f64)> = vec![];
+    let mut materials: HashMap<String, Materials> = HashMap::new();
+    let mut mat: Materials = default_mat;
+    let mut triangles: Vec<Triangle> = vec![];
+    let mut groups: Vec<(String, Range<usize>)> = vec![];
+    let mut group: String = String::from("default");
+    let mut group_start: usize = 0;
+
+    for (n, raw) in src.lines().enumerate() {
+        p.line = n + 1;
+        let line: &str = raw.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword: &str = match tokens.next() {
+            Some(k) => k,
+            None => continue
+        };
+        let args: Vec<&str> = tokens.collect();
+        match keyword {
+            "v" => {
+                let xs: Vec<f64> = p.floats(&args, 3, 4, "vertex")?;                 // Optional w is ignored
+                positions.push(Vec3(xs[0], xs[1], xs[2]));
+            },
+            "vn" => {
+                let n: Vec3 = p.vec3(&args, "normal")?;
+                if (!(n.len() > 0. && n.len().is_finite())) {                       // Would turn into NaN shading normals
+                    return Err(p.err(String::from("normal must have a finite, nonzero length")));
+                }
+                normals.push(n.unit());
+            },
+            "vt" => {
+                let xs: Vec<f64> = p.floats(&args, 1, 3, "texture coordinate")?;
+                uvs.push((xs[0], xs.get(1).copied().unwrap_or(0.)));
+            },
+            "f" => {
+                if (args.len() < 3) {
+                    return Err(p.err(format!("face needs at least 3 vertices, got {}", args.len())));
+                }
+                let mut corners: Vec<Corner> = vec![];
+                for arg in args.iter() {                                            // v, v/vt, v//vn or v/vt/vn
+                    let mut refs = arg.split('/');
+                    let v: usize = p.index(refs.next().unwrap_or(""), positions.len(), "vertex")?;
+                    let vt: Option<(f64, f64)> = match refs.next() {
+                        Some(s) if (!s.is_empty()) => Some(uvs[p.index(s, uvs.len(), "texture coordinate")?]),
+                        _ => None
+                    };
+                    let vn: Option<Vec3> = match refs.next() {
+                        Some(s) if (!s.is_empty()) => Some(normals[p.index(s, normals.len(), "normal")?]),
+                        _ => None
+                    };
+                    if (refs.next().is_some()) {
+                        return Err(p.err(format!("malformed face vertex '{}'", arg)));
+                    }
+                    corners.push((positions[v], vt, vn));
+                }
+                for k in 1..corners.len() - 1 {                                     // Fan triangulation, fine for the convex polygons exporters write
+                    let tri: [Corner; 3] = [corners[0], corners[k], corners[k + 1]];
+                    let n: Option<[Vec3; 3]> = match (tri[0].2, tri[1].2, tri[2].2) {
+                        (Some(n0), Some(n1), Some(n2)) => Some([n0, n1, n2]),
+                        _ => None
+                    };
+                    let uv: Option<[(f64, f64); 3]> = match (tri[0].1, tri[1].1, tri[2].1) {
+                        (Some(t0), Some(t1), Some(t2)) => Some([t0, t1, t2]),
+                        _ => None
+                    };
+                    triangles.push(Triangle {v: [tri[0].0, tri[1].0, tri[2].0], n, uv, motion: None, mat: mat.clone()});
+                }
+            },
+            "g" | "o" => {
+                if (triangles.len() > group_start) {
+                    groups.push((group, group_start..triangles.len()));
+                }
+                group = if (args.is_empty()) {String::from("default")} else {args.join(" ")};
+                group_start = triangles.len();
+            },
+            "usemtl" => {
+                let name: String = args.join(" ");
+                mat = materials.get(&name).ok_or_else(|| p.err(format!("unknown material '{}'", name)))?.clone();
+            },
+            "mtllib" => {
+                for lib in args.iter() {
+                    materials.extend(load_mtl(&dir.join(lib))?);
+                }
+            },
+            "s" | "l" | "p" | "cstype" | "deg" | "curv" | "curv2" | "surf" | "vp" | "mg" | "usemap" => {}, // Smoothing groups and free-form geometry aren't supported
+            _ => return Err(p.err(format!("unknown statement '{}'", keyword)))
+        }
+    }
+    if (triangles.len() > group_start) {
+        groups.push((group, group_start..triangles.len()));
+    }
+    Ok(Mesh {triangles, groups})
+}
+
+struct MtlParams { // The subset of .mtl parameters we can map onto our materials
+    kd: Vec3,
+    map_kd: Option<ImageTexture>, // Replaces kd when present
+    ks: Vec3,
+    ke: Vec3,
+    ns: f64,
+    ni: Option<f64>,
+    d: f64,
+    illum: u32
+}
+
+impl MtlParams {
+    fn to_material(&self) -> Materials {
+        if (!self.ke.near_zero()) {
+            Materials::DiffuseLight(DiffuseLight {color: Textures::Solid(self.ke), intensity: 1.})
+        } else if (self.d < 1. || matches!(self.illum, 4 | 6 | 7 | 9)) {         // Transparent or one of the refraction illumination models
+            Materials::Dielectric(Dielectric {color: Textures::Solid(Vec3(1., 1., 1.)), refraction_idx: self.ni.unwrap_or(1.5)})
+        } else if (self.illum == 3 || self.ks.max_coord() > self.kd.max_coord()) { // Mirror-like: specular response dominates diffuse
+            let fuzz: f64 = (2./(self.ns + 2.)).sqrt().min(1.);                     // Phong exponent to a roughness-like fuzz
+            Materials::Metal(Metal {color: Textures::Solid(self.ks), fuzz})
+        } else {
+            let color: Textures = match &self.map_kd {
+                Some(img) => Textures::Image(img.clone()),
+                None => Textures::Solid(self.kd)
+            };
+            Materials::Lambertian(Lambertian {color})
+        }
+    }
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, Materials>, ObjError> {
+    let src: String = fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let dir: &Path = path.parent().unwrap_or(Path::new(""));
+    let mut p: Parser = Parser {path, line: 0};
+    let mut materials: HashMap<String, Materials> = HashMap::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for (n, raw) in src.lines().enumerate() {
+        p.line = n + 1;
+        let line: &str = raw.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword: &str = match tokens.next() {
+            Some(k) => k,
+            None => continue
+        };
+        let args: Vec<&str> = tokens.collect();
+        if (keyword == "newmtl") {
+            if let Some((name, params)) = current.take() {
+                materials.insert(name, params.to_material());
+            }
+            let defaults: MtlParams = MtlParams {
+                kd: Vec3(0.8, 0.8, 0.8),
+                map_kd: None,
+                ks: Vec3(0., 0., 0.),
+                ke: Vec3(0., 0., 0.),
+                ns: 0.,
+                ni: None,
+                d: 1.,
+                illum: 2
+            };
+            current = Some((args.join(" "), defaults));
+            continue;
+        }
+        let params: &mut MtlParams = match current.as_mut() {
+            Some((_, params)) => params,
+            None => return Err(p.err(format!("'{}' before any newmtl", keyword)))
+        };
+        match keyword {
+            "Kd" => params.kd = p.vec3(&args, "Kd")?,
+            "Ks" => params.ks = p.vec3(&args, "Ks")?,
+            "Ke" => params.ke = p.vec3(&args, "Ke")?,
+            "Ns" => params.ns = p.floats(&args, 1, 1, "Ns")?[0],
+            "Ni" => params.ni = Some(p.floats(&args, 1, 1, "Ni")?[0]),
+            "d" => params.d = p.floats(&args, 1, 1, "d")?[0],
+            "Tr" => params.d = 1. - p.floats(&args, 1, 1, "Tr")?[0],
+            "map_Kd" => {                                                           // Options such as -clamp may precede the file name
+                let file: &str = args.last().ok_or_else(|| p.err(String::from("map_Kd expects a file name")))?;
+                let img: ImageTexture = ImageTexture::load(&dir.join(file), true).map_err(|e| p.err(format!("can't load texture '{}': {}", file, e)))?;
+                params.map_kd = Some(img);
+            },
+            "illum" => params.illum = args.first().and_then(|a| a.parse().ok()).ok_or_else(|| p.err(String::from("illum expects an integer")))?,
+            _ => {}                                                                 // Texture maps and other parameters we can't represent yet
+        }
+    }
+    if let Some((name, params)) = current {
+        materials.insert(name, params.to_material());
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn load(name: &str, obj: &str, mtl: &str) -> Result<Mesh, ObjError> { // Writes the files next to each other in a scratch directory
+        let dir: PathBuf = env::temp_dir().join(format!("raytracer-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("test.mtl"), mtl).unwrap();
+        fs::write(dir.join("test.obj"), obj).unwrap();
+        let result: Result<Mesh, ObjError> = load_obj(&dir.join("test.obj"), Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.5, 0.5, 0.5))}));
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn error_line(result: Result<Mesh, ObjError>) -> (String, usize, String) {
+        match result {
+            Err(ObjError::Parse {path, line, msg}) => (path.file_name().unwrap().to_string_lossy().into_owned(), line, msg),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("loaded an invalid file")
+        }
+    }
+
+    const SQUARE: &str = "mtllib test.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\n";
+
+    #[test]
+    fn loads_quads_as_triangles() {
+        let mesh: Mesh = load("quad", &format!("{}usemtl red\nf 1//1 2//1 3//1 4//1\n", SQUARE), "newmtl red\nKd 1 0 0\n").unwrap();
+        assert!(mesh.triangles.len() == 2 && mesh.triangles.iter().all(|t| t.n.is_some()));
+    }
+
+    #[test]
+    fn reports_the_offending_line() {
+        let cases: [(&str, &str, &str, &str, usize, &str); 6] = [
+            ("number", "v 0 0 0\nv 1 x 0\n", "", "test.obj", 2, "invalid number"),
+            ("index", &format!("{}f 1 2 5\n", SQUARE), "", "test.obj", 7, "vertex index 5 out of range"),
+            ("normal", "v 0 0 0\n\nvn 0 0 0\n", "", "test.obj", 3, "nonzero length"),
+            ("material", &format!("{}usemtl blue\n", SQUARE), "newmtl red\n", "test.obj", 7, "unknown material"),
+            ("face", "v 0 0 0\nv 1 0 0\n# comment\nf 1 2\n", "", "test.obj", 4, "at least 3 vertices"),
+            ("mtl", SQUARE, "newmtl red\nKd 1 0\n", "test.mtl", 2, "Kd expects")
+        ];
+        for (name, obj, mtl, file, line, msg) in cases {
+            let (got_file, got_line, got_msg): (String, usize, String) = error_line(load(name, obj, mtl));
+            assert!(got_file == file && got_line == line && got_msg.contains(msg), "{}: got {}:{}: {}", name, got_file, got_line, got_msg);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy)]
+pub struct Onb { // Orthonormal basis with w aligned to a given direction
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Onb {
+        let w: Vec3 = n.unit();
+        let a: Vec3 = if (w.0.abs() > 0.9) {Vec3(0., 1., 0.)} else {Vec3(1., 0., 0.)}; // Any vector not parallel to w
+        let v: Vec3 = w.x(a).unit();
+        let u: Vec3 = w.x(v);
+        Onb {u, v, w}
+    }
+
+    pub fn local(self, a: Vec3) -> Vec3 { // Basis coordinates to world space
+        a.0*self.u + a.1*self.v + a.2*self.w
+    }
+}
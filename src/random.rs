@@ -1,7 +1,28 @@
+pub const DEFAULT_SEED: u64 = 0; // Seed of every render that doesn't choose one, so reruns give identical images
+
 pub fn random() -> f64 {
     fastrand::f64()
 }
 
 pub fn random_in(min: f64, max: f64) -> f64 {
     min + (max - min)*fastrand::f64()
-}
\ No newline at end of file
+}
+
+pub fn random_u64() -> u64 {
+    fastrand::u64(..)
+}
+
+pub fn seed_rng(seed: u64) { // Reseeds the calling thread's generator
+    fastrand::seed(seed)
+}
+
+pub fn hash(a: u64, b: u64) -> u64 { // SplitMix64 finalizer over both inputs, used to derive independent seeds
+    let mut z: u64 = a ^ b.wrapping_add(0x9e3779b97f4a7c15).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+pub fn hash_str(s: &str) -> u64 { // FNV-1a, stable across runs and builds unlike the std hashers
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
@@ -3,7 +3,8 @@ use crate::vec3::Vec3;
 #[derive(Clone, Copy)]
 pub struct Ray {
     pub ori: Vec3,
-    pub dir: Vec3
+    pub dir: Vec3,
+    pub time: f64 // Instant within the camera shutter at which the ray was cast
 }
 
 impl Ray {
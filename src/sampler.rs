@@ -0,0 +1,252 @@
+use crate::random::{random, seed_rng, hash};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Independent, // Plain uniform random numbers
+    Stratified,  // Correlated multi-jittering (Kensler 2013) for any sample count
+    Halton,      // Radical inverses in prime bases with per-pixel Owen scrambled digits
+    Sobol        // Padded 2D Sobol with hash-based Owen scrambling (Burley 2020), best at powers of two
+}
+
+pub const NAMES: [&str; 4] = ["independent", "stratified", "halton", "sobol"];
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131
+];
+
+const ONE_MINUS_EPSILON: f64 = 1. - f64::EPSILON/2.; // Largest f64 below 1
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None
+        }
+    }
+}
+
+pub struct Sampler { // Sample values for one pixel sample at a time, handed out dimension by dimension
+    kind: SamplerKind,
+    spp: u32,     // Samples per pixel, the sequences are stratified over this many
+    seed: u64,
+    pixel: u64,   // Hash of the seed and the current pixel, decorrelating neighbors
+    index: u32,   // Current sample within the pixel
+    dim: u32      // Next dimension to hand out
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, spp: u32, seed: u64) -> Sampler {
+        Sampler {kind, spp: spp.max(1), seed, pixel: 0, index: 0, dim: 0}
+    }
+
+    pub fn start_pixel_sample(&mut self, i: u32, j: u32, index: u32) { // Also reseeds the thread's generator, so whatever draws from random.rs depends only on the pixel and sample, never on the thread or the order of the work
+        self.pixel = hash(self.seed, ((j as u64) << 32) | i as u64);
+        self.index = index;
+        self.dim = 0;
+        seed_rng(hash(self.pixel, index as u64));
+    }
+
+    pub fn get_1d(&mut self) -> f64 {
+        let dim: u32 = self.dim;
+        self.dim += 1;
+        let h: u64 = hash(self.pixel, dim as u64);
+        let u: f64 = match self.kind {
+            SamplerKind::Independent => random(),
+            SamplerKind::Stratified => {
+                let stratum: u32 = permute(self.index, self.spp, h as u32);
+                (stratum as f64 + hash_float(hash(h, stratum as u64), 1))/self.spp as f64
+            },
+            SamplerKind::Halton => match PRIMES.get(dim as usize) {
+                Some(&base) => scrambled_radical_inverse(base, self.index, h as u32),
+                None => random()                                                    // Past the table the dimensions are too correlated to be worth it
+            },
+            SamplerKind::Sobol => {
+                let index: u32 = nested_uniform_scramble(self.index, h as u32);
+                nested_uniform_scramble(index.reverse_bits(), (h >> 32) as u32) as f64/4294967296.
+            }
+        };
+        u.min(ONE_MINUS_EPSILON)
+    }
+
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        let dim: u32 = self.dim;
+        self.dim += 2;
+        let h: u64 = hash(self.pixel, dim as u64);
+        let (u, v): (f64, f64) = match self.kind {
+            SamplerKind::Independent => (random(), random()),
+            SamplerKind::Stratified => cmj(self.index, self.spp, h),
+            SamplerKind::Halton => match (PRIMES.get(dim as usize), PRIMES.get(dim as usize + 1)) {
+                (Some(&b0), Some(&b1)) => (
+                    scrambled_radical_inverse(b0, self.index, h as u32),
+                    scrambled_radical_inverse(b1, self.index, (h >> 32) as u32)
+                ),
+                _ => (random(), random())
+            },
+            SamplerKind::Sobol => {                                                 // Each pair of dimensions shuffles the index on its own, padding 2D Sobol into any dimension count
+                let index: u32 = nested_uniform_scramble(self.index, h as u32);
+                let (x, y): (u32, u32) = sobol_2d(index);
+                let h2: u64 = hash(h, 1);
+                (
+                    nested_uniform_scramble(x, (h >> 32) as u32) as f64/4294967296.,
+                    nested_uniform_scramble(y, h2 as u32) as f64/4294967296.
+                )
+            }
+        };
+        (u.min(ONE_MINUS_EPSILON), v.min(ONE_MINUS_EPSILON))
+    }
+}
+
+fn hash_float(h: u64, salt: u64) -> f64 { // Uniform in [0, 1) from a hash
+    (hash(h, salt) >> 11) as f64/(1u64 << 53) as f64
+}
+
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u32) -> f64 { // Digits of index in base mirrored around the radix point, each permuted depending on the ones before it
+    let inv_base: f64 = 1./base as f64;
+    let mut inv_base_n: f64 = 1.;
+    let mut reversed: u64 = 0;
+    while (inv_base_n > 1e-9) {                                                     // Past the last digit of index the zeros get scrambled too, or every value would end in the same digits
+        let next: u32 = index/base;
+        let digit_seed: u32 = hash(seed as u64, reversed) as u32;
+        let digit: u32 = permute(index - next*base, base, digit_seed);
+        reversed = reversed*base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    reversed as f64*inv_base_n
+}
+
+fn sobol_2d(index: u32) -> (u32, u32) { // First two Sobol dimensions, van der Corput and the one from the polynomial x + 1
+    let x: u32 = index.reverse_bits();
+    let mut y: u32 = 0;
+    let mut v: u32 = 1 << 31;
+    let mut i: u32 = index;
+    while (i != 0) {
+        if (i & 1 != 0) {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 { // Each bit only depends on the ones below it, so this scrambles reversed values like an Owen tree
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 { // Owen scrambling of a 0.32 fixed point value
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn permute(mut i: u32, l: u32, p: u32) -> u32 { // Random permutation of 0..l picked by p, evaluated at i (Kensler 2013)
+    let mut w: u32 = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {                                                                          // Cycle walking until the value lands back inside 0..l
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if (i < l) {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+fn cmj(s: u32, n: u32, h: u64) -> (f64, f64) { // Sample s out of n on an m by k grid, stratified in 2D and in both 1D projections
+    let m: u32 = (1..=n.isqrt()).rev().find(|&d| n.is_multiple_of(d)).unwrap_or(1); // Closest divisors to a square, so every cell gets a sample; a prime n falls back to n-rooks
+    let k: u32 = n/m;
+    let p: u32 = h as u32;
+    let s: u32 = permute(s, n, p.wrapping_mul(0x51633e2d));
+    let sx: u32 = permute(s % m, m, p.wrapping_mul(0xa511e9b3));
+    let sy: u32 = permute(s / m, k, p.wrapping_mul(0x63d83595));
+    let hs: u64 = hash(h, s as u64);                                                // Each sample jitters on its own within its cell
+    let jx: f64 = hash_float(hs, 1);
+    let jy: f64 = hash_float(hs, 2);
+    (
+        ((s % m) as f64 + (sy as f64 + jx)/k as f64)/m as f64,
+        ((s / m) as f64 + (sx as f64 + jy)/m as f64)/k as f64
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_in_unit_interval() {
+        for kind in NAMES.iter().map(|name| SamplerKind::from_name(name).unwrap()) {
+            for spp in [1, 7, 16, 100] {
+                let mut sampler: Sampler = Sampler::new(kind, spp, 3);
+                for index in 0..spp {
+                    sampler.start_pixel_sample(5, 9, index);
+                    for _ in 0..20 {                                                // Past the Halton table too
+                        let u: f64 = sampler.get_1d();
+                        let (v, w): (f64, f64) = sampler.get_2d();
+                        assert!([u, v, w].iter().all(|x| (0. ..1.).contains(x)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_covers_every_stratum() {
+        let spp: u32 = 12;
+        let mut sampler: Sampler = Sampler::new(SamplerKind::Stratified, spp, 3);
+        let mut strata: Vec<u32> = (0..spp).map(|index| {
+            sampler.start_pixel_sample(1, 2, index);
+            (sampler.get_1d()*spp as f64) as u32
+        }).collect();
+        strata.sort();
+        assert!(strata == (0..spp).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn stratified_fills_every_cell() {
+        let counts: [u32; 8] = [2, 3, 5, 6, 7, 12, 16, 50];                         // Primes, rectangles and squares
+        for spp in counts {
+            let m: u32 = (1..=spp.isqrt()).rev().find(|&d| spp.is_multiple_of(d)).unwrap();
+            let k: u32 = spp/m;
+            let mut sampler: Sampler = Sampler::new(SamplerKind::Stratified, spp, 3);
+            let points: Vec<(f64, f64)> = (0..spp).map(|index| {
+                sampler.start_pixel_sample(1, 2, index);
+                sampler.get_2d()
+            }).collect();
+            let covers = |mut ids: Vec<u32>| {
+                ids.sort();
+                ids == (0..spp).collect::<Vec<u32>>()
+            };
+            assert!(covers(points.iter().map(|(u, v)| ((u*m as f64) as u32)*k + (v*k as f64) as u32).collect()), "{} samples leave a cell of the {} by {} grid empty", spp, m, k);
+            assert!(covers(points.iter().map(|(u, _)| (u*spp as f64) as u32).collect()));
+            assert!(covers(points.iter().map(|(_, v)| (v*spp as f64) as u32).collect()));
+        }
+    }
+}
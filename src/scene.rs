@@ -0,0 +1,674 @@
+use std::fmt;
+use std::fs;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use toml::{Spanned, Table};
+
+use crate::vec3::Vec3;
+use crate::sphere::Sphere;
+use crate::triangle::Triangle;
+use crate::world::{World, Surfaces, Background};
+use crate::camera::{CameraBuilder, Projection};
+use crate::materials::{Materials, Lambertian, Metal, Dielectric, DiffuseLight};
+use crate::obj::{load_obj, Mesh, ObjError};
+use crate::random::{random, random_in, seed_rng, hash, hash_str, DEFAULT_SEED};
+use crate::texture::{Textures, Checker, UvChecker, ImageTexture, Filter, Wrap, Noise, Marble, Wood};
+use crate::noise::Perlin;
+use crate::denoise::Denoiser;
+use crate::color::{Format, FORMATS};
+use crate::tonemap::{ToneMap, NAMES as TONE_MAPS};
+use crate::sampler::{SamplerKind, NAMES as SAMPLERS};
+use crate::film::{PixelFilter, NAMES as FILTERS, MIN_RADIUS};
+use crate::adaptive::Adaptive;
+
+pub struct Scene {
+    pub world: World,
+    pub camera: CameraBuilder // Camera as described by the scene, still open to overrides
+}
+
+pub const BUILTINS: [&str; 2] = ["spheres", "bouncing"]; // Procedural scenes that can be selected by name instead of a file
+
+pub fn builtin(name: &str, seed: Option<u64>) -> Option<Scene> { // seed drives both the layout and the render, DEFAULT_SEED if None
+    let seed: u64 = seed.unwrap_or(DEFAULT_SEED);
+    match name {
+        "spheres" => Some(random_spheres(false, seed)),
+        "bouncing" => Some(random_spheres(true, seed)),
+        _ => None
+    }
+}
+
+fn random_spheres(bouncing: bool, seed: u64) -> Scene { // Final scene of Ray Tracing in One Weekend, with the small diffuse spheres jumping during the exposure if bouncing
+    seed_rng(seed);
+    let mut surfaces: Vec<Surfaces> = vec![];
+    let ground_material: Materials = Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.5, 0.5, 0.5))});
+    let material1: Materials = Materials::Dielectric(Dielectric {color: Textures::Solid(Vec3(1., 1., 1.)), refraction_idx: 1.5});
+    let material2: Materials = Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.4, 0.2, 0.1))});
+    let material3: Materials = Materials::Metal(Metal {color: Textures::Solid(Vec3(0.7, 0.6, 0.5)), fuzz: 0.});
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: f64 = random();
+            let center: Vec3 = Vec3(a as f64 + 0.9*random(), 0.2, b as f64 + 0.9*random());
+            if ((center - Vec3(4., 0.2, 0.)).len() > 0.9) {
+                let mut sphere_material: Materials;
+                if (choose_mat < 0.8) {
+                    // diffuse
+                    let albedo: Vec3 = Vec3::random().coord_mul(Vec3::random());
+                    sphere_material = Materials::Lambertian(Lambertian {color: Textures::Solid(albedo)});
+                    let bounce: f64 = random_in(0., 0.5);                           // Drawn either way so both scenes place the same spheres
+                    let center1: Option<Vec3> = if (bouncing) {Some(center + Vec3(0., bounce, 0.))} else {None};
+                    surfaces.push(Surfaces::Sphere(Sphere {center, center1, radius: 0.2, mat: sphere_material}));
+                } else if (choose_mat < 0.95) {
+                    // metal
+                    let albedo: Vec3 = Vec3::random_in(0.5, 1.);
+                    let fuzz: f64 = random_in(0., 0.5);
+                    sphere_material = Materials::Metal(Metal {color: Textures::Solid(albedo), fuzz});
+                    surfaces.push(Surfaces::Sphere(Sphere {center, center1: None, radius: 0.2, mat: sphere_material}));
+                } else {
+                    // glass
+                    sphere_material = Materials::Dielectric(Dielectric {color: Textures::Solid(Vec3(1., 1., 1.)), refraction_idx: 1.5});
+                    surfaces.push(Surfaces::Sphere(Sphere {center, center1: None, radius: 0.2, mat: sphere_material}));
+                }
+            }
+        }
+    }
+
+    surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(0., -1000., 0.), center1: None, radius: 1000., mat: ground_material}));
+    surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(0., 1., 0.), center1: None, radius: 1., mat: material1}));
+    surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(-4., 1., 0.), center1: None, radius: 1., mat: material2}));
+    surfaces.push(Surfaces::Sphere(Sphere {center: Vec3(4., 1., 0.), center1: None, radius: 1., mat: material3}));
+
+    Scene {
+        world: World::new(surfaces, Background::Sky),
+        camera: CameraBuilder::new()
+            .look_from(Vec3(13., 2., 3.))
+            .look_at(Vec3(0., 0., 0.))
+            .defocus_angle(0.65)
+            .focus_distance(10.)
+            .aspect_ratio(16.0/9.0)
+            .image_width(2000)
+            .max_depth(Some(50))
+            .projection(Projection::Perspective {vertical_fov: 20.})
+            .samples(64)
+            .seed(seed)
+            .scene_fingerprint(hash_str(if (bouncing) {"bouncing"} else {"spheres"}))
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Parse {path: PathBuf, line: usize, col: usize, msg: String},       // Malformed TOML or fields of the wrong shape
+    Invalid {path: PathBuf, line: usize, field: String, msg: String}, // Well-formed but meaningless values
+    Mesh(ObjError)
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Parse {path, line, col, msg} => write!(f, "{}:{}:{}: {}", path.display(), line, col, msg),
+            SceneError::Invalid {path, line, field, msg} => write!(f, "{}:{}: {}: {}", path.display(), line, field, msg),
+            SceneError::Mesh(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Spanned<CameraDesc>,
+    render: Option<Spanned<RenderDesc>>,
+    background: Option<Spanned<BackgroundDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<Table>>, // Kept as raw tables until their type is known, so errors can point at the entry
+    #[serde(default)]
+    surfaces: Vec<Spanned<Table>>
+}
+
+type Fields = HashMap<String, Spanned<IgnoredAny>>; // Where each field of a table is written
+
+#[derive(Deserialize)]
+struct SpanIndex { // Field positions, parsed apart from SceneDesc since the tagged and untagged enums there lose them
+    camera: Option<Fields>,
+    render: Option<Fields>,
+    #[serde(default)]
+    materials: HashMap<String, Fields>,
+    #[serde(default)]
+    surfaces: Vec<Fields>
+}
+
+fn field_span(fields: Option<&Fields>, name: &str, table: &Range<usize>) -> Range<usize> { // Span of the field if it's written out, of its table otherwise
+    fields.and_then(|f| f.get(name)).map_or(table.clone(), |s| s.span())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    #[serde(default)]
+    projection: ProjectionDesc,
+    #[serde(default = "default_fov")]
+    vertical_fov: f64,                                                             // Perspective only
+    view_width: Option<f64>,                                                       // Orthographic only, required there
+    #[serde(default = "default_fisheye_fov")]
+    fov: f64,                                                                      // Fisheye only
+    #[serde(default)]
+    defocus_angle: f64,
+    focus_distance: Option<f64>,                                                   // Defaults to the distance to look_at
+    #[serde(default = "default_aspect_ratio")]
+    aspect_ratio: f64,
+    #[serde(default = "default_shutter")]
+    shutter: [f64; 2]                                                              // Moving surfaces are keyed at times 0 and 1
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ProjectionDesc {
+    #[default]
+    Perspective,
+    Orthographic,
+    Equirectangular,
+    Fisheye
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    width: Option<u32>,
+    samples: Option<u32>,
+    sampler: Option<String>,                                                       // Sobol if missing
+    filter: Option<String>,                                                        // Box if missing
+    filter_radius: Option<f64>,                                                    // The filter's own default if missing
+    #[serde(default)]
+    adaptive: bool,                                                                // Stop sampling converged pixels, samples being the cap
+    min_samples: Option<u32>,                                                      // Only read with adaptive
+    threshold: Option<f64>,                                                        // Only read with adaptive
+    #[serde(default)]
+    heatmap: bool,                                                                 // Also write the samples taken per pixel
+    checkpoint: Option<String>,                                                    // Render progressively, saving progress there after every pass
+    pass_samples: Option<u32>,                                                     // Render progressively in passes this big
+    max_depth: Option<u32>,                                                        // Unbounded, left to Russian roulette, if missing
+    threads: Option<usize>,
+    seed: Option<u64>,                                                             // DEFAULT_SEED if missing
+    #[serde(default)]
+    aovs: bool,                                                                    // Also write the auxiliary passes
+    #[serde(default)]
+    denoise: bool,                                                                 // Also write a denoised copy
+    tonemap: Option<String>,                                                       // Clamp if missing
+    #[serde(default = "four")]
+    white: f64,                                                                    // Only read by reinhard-extended
+    #[serde(default)]
+    exposure: f64,
+    format: Option<String>,                                                        // Guessed from the output extension if missing
+    output: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Named(String), // "sky"
+    Color([f64; 3])
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {color: ColorDesc},
+    Metal {color: ColorDesc, #[serde(default)] fuzz: f64},
+    Dielectric {#[serde(default = "white")] color: ColorDesc, refraction_idx: f64},
+    Light {#[serde(default = "white")] color: ColorDesc, #[serde(default = "one")] intensity: f64}
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDesc { // Either a plain [r, g, b] or a texture table
+    Rgb([f64; 3]),
+    Texture(TextureDesc)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Checker {#[serde(default = "one")] scale: f64, even: [f64; 3], odd: [f64; 3]},
+    UvChecker {#[serde(default = "ten")] scale: f64, even: [f64; 3], odd: [f64; 3]},
+    Noise {#[serde(default = "one")] scale: f64, #[serde(default = "seven")] octaves: u32, #[serde(default = "black")] low: [f64; 3], #[serde(default = "white_rgb")] high: [f64; 3]},
+    Marble {#[serde(default = "one")] scale: f64, #[serde(default = "seven")] octaves: u32, #[serde(default = "black")] low: [f64; 3], #[serde(default = "white_rgb")] high: [f64; 3]},
+    Wood {#[serde(default = "ten")] scale: f64, #[serde(default = "seven")] octaves: u32, #[serde(default = "light_wood")] low: [f64; 3], #[serde(default = "dark_wood")] high: [f64; 3]},
+    Image {
+        path: String,
+        #[serde(default)] filter: FilterDesc,
+        #[serde(default)] wrap: WrapDesc,
+        #[serde(default = "yes")] srgb: bool
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum FilterDesc {
+    Nearest,
+    #[default]
+    Bilinear
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum WrapDesc {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum SurfaceDesc {
+    Sphere {center: [f64; 3], center1: Option<[f64; 3]>, radius: f64, material: String}, // center1 is reached at time 1
+    Triangle {vertices: [[f64; 3]; 3], normals: Option<[[f64; 3]; 3]>, motion: Option<[f64; 3]>, material: String}, // motion is the translation reached at time 1
+    Quad {corner: [f64; 3], u: [f64; 3], v: [f64; 3], motion: Option<[f64; 3]>, material: String}, // Parallelogram spanned by u and v, split in two triangles
+    Mesh {path: String, material: Option<String>, #[serde(default = "one")] scale: f64, #[serde(default)] translate: [f64; 3], motion: Option<[f64; 3]>}
+}
+
+fn default_up() -> [f64; 3] {[0., 1., 0.]}
+fn default_fov() -> f64 {90.}
+fn default_fisheye_fov() -> f64 {180.}
+fn default_shutter() -> [f64; 2] {[0., 1.]}
+fn default_aspect_ratio() -> f64 {16./9.}
+fn white() -> ColorDesc {ColorDesc::Rgb([1., 1., 1.])}
+fn one() -> f64 {1.}
+fn ten() -> f64 {10.}
+fn yes() -> bool {true}
+fn four() -> f64 {4.}
+fn seven() -> u32 {7}
+fn black() -> [f64; 3] {[0., 0., 0.]}
+fn white_rgb() -> [f64; 3] {[1., 1., 1.]}
+fn light_wood() -> [f64; 3] {[0.75, 0.55, 0.33]}
+fn dark_wood() -> [f64; 3] {[0.4, 0.25, 0.12]}
+
+fn vec3(a: [f64; 3]) -> Vec3 {
+    Vec3(a[0], a[1], a[2])
+}
+
+fn collinear(a: Vec3, b: Vec3) -> bool { // Relative to the lengths, so it holds at any scene scale
+    let area: f64 = a.x(b).len();
+    area.is_nan() || area <= 1e-12*a.len()*b.len()
+}
+
+struct Validator<'a> { // Turns byte spans into line numbers for errors found after parsing
+    path: &'a Path,
+    src: &'a str
+}
+
+impl Validator<'_> {
+    fn line(&self, offset: usize) -> usize {
+        self.src[..offset.min(self.src.len())].matches('\n').count() + 1
+    }
+
+    fn entry<T: DeserializeOwned>(&self, entry: Spanned<Table>, field: &str) -> Result<(T, Range<usize>), SceneError> {
+        let span: Range<usize> = entry.span();
+        match entry.into_inner().try_into::<T>() {
+            Ok(desc) => Ok((desc, span)),
+            Err(e) => Err(SceneError::Invalid {path: self.path.to_path_buf(), line: self.line(span.start), field: field.to_string(), msg: e.message().to_string()})
+        }
+    }
+
+    fn texture(&self, desc: ColorDesc, dir: &Path, seed: u64, span: Range<usize>, field: String) -> Result<Textures, SceneError> { // seed picks the noise lattice
+        let texture: Textures = match desc {
+            ColorDesc::Rgb(c) => Textures::Solid(vec3(c)),
+            ColorDesc::Texture(TextureDesc::Checker {scale, even, odd}) => {
+                self.check(scale > 0., span, field + ".scale", "must be positive")?;
+                Textures::Checker(Checker {scale, even: vec3(even), odd: vec3(odd)})
+            },
+            ColorDesc::Texture(TextureDesc::UvChecker {scale, even, odd}) => {
+                self.check(scale > 0., span, field + ".scale", "must be positive")?;
+                Textures::UvChecker(UvChecker {scale, even: vec3(even), odd: vec3(odd)})
+            },
+            ColorDesc::Texture(TextureDesc::Noise {scale, octaves, low, high}) => {
+                self.check(scale > 0., span, field + ".scale", "must be positive")?;
+                Textures::Noise(Noise {perlin: Arc::new(Perlin::new(seed)), scale, octaves, low: vec3(low), high: vec3(high)})
+            },
+            ColorDesc::Texture(TextureDesc::Marble {scale, octaves, low, high}) => {
+                self.check(scale > 0., span, field + ".scale", "must be positive")?;
+                Textures::Marble(Marble {perlin: Arc::new(Perlin::new(seed)), scale, octaves, low: vec3(low), high: vec3(high)})
+            },
+            ColorDesc::Texture(TextureDesc::Wood {scale, octaves, low, high}) => {
+                self.check(scale > 0., span, field + ".scale", "must be positive")?;
+                Textures::Wood(Wood {perlin: Arc::new(Perlin::new(seed)), scale, octaves, low: vec3(low), high: vec3(high)})
+            },
+            ColorDesc::Texture(TextureDesc::Image {path, filter, wrap, srgb}) => match ImageTexture::load(&dir.join(&path), srgb) {
+                Ok(mut img) => {
+                    img.filter = match filter {
+                        FilterDesc::Nearest => Filter::Nearest,
+                        FilterDesc::Bilinear => Filter::Bilinear
+                    };
+                    img.wrap = match wrap {
+                        WrapDesc::Repeat => Wrap::Repeat,
+                        WrapDesc::Clamp => Wrap::Clamp,
+                        WrapDesc::Mirror => Wrap::Mirror
+                    };
+                    Textures::Image(img)
+                },
+                Err(e) => return Err(SceneError::Invalid {path: self.path.to_path_buf(), line: self.line(span.start), field: field + ".path", msg: format!("can't load '{}': {}", path, e)})
+            }
+        };
+        Ok(texture)
+    }
+
+    fn check(&self, ok: bool, span: Range<usize>, field: String, msg: &str) -> Result<(), SceneError> {
+        if (ok) {
+            Ok(())
+        } else {
+            Err(SceneError::Invalid {path: self.path.to_path_buf(), line: self.line(span.start), field, msg: msg.to_string()})
+        }
+    }
+}
+
+fn fingerprint(src: &str) -> u64 { // Hash of everything in the scene file but the render settings, normalized so comments and formatting don't count
+    let mut table: Table = toml::from_str(src).unwrap_or_default();
+    table.remove("render");
+    hash_str(&table.to_string())
+}
+
+pub fn load_scene(path: &Path, seed: Option<u64>) -> Result<Scene, SceneError> { // seed overrides render.seed
+    let src: String = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    let desc: SceneDesc = toml::from_str(&src).map_err(|e| {
+        let offset: usize = e.span().map_or(0, |s| s.start);
+        let line: usize = src[..offset].matches('\n').count() + 1;
+        let col: usize = offset - src[..offset].rfind('\n').map_or(0, |i| i + 1) + 1;
+        SceneError::Parse {path: path.to_path_buf(), line, col, msg: e.message().to_string()}
+    })?;
+    let spans: SpanIndex = toml::from_str(&src).map_err(|e| SceneError::Parse {path: path.to_path_buf(), line: 1, col: 1, msg: e.message().to_string()})?;
+    let v: Validator = Validator {path, src: &src};
+    let dir: &Path = path.parent().unwrap_or(Path::new(""));
+
+    let cam_span = desc.camera.span();
+    let cam: CameraDesc = desc.camera.into_inner();
+    let cam_at = |f: &str| field_span(spans.camera.as_ref(), f, &cam_span);
+    let (look_from, look_at): (Vec3, Vec3) = (vec3(cam.look_from), vec3(cam.look_at));
+    v.check((look_from - look_at).len() > 1e-12*look_from.len().max(look_at.len()), cam_at("look_at"), String::from("camera.look_at"), "must differ from look_from")?;
+    let projection: Projection = match cam.projection {
+        ProjectionDesc::Perspective => {
+            v.check(cam.vertical_fov > 0. && cam.vertical_fov < 180., cam_at("vertical_fov"), String::from("camera.vertical_fov"), "must be between 0 and 180 degrees")?;
+            Projection::Perspective {vertical_fov: cam.vertical_fov}
+        },
+        ProjectionDesc::Orthographic => {
+            let view_width: f64 = cam.view_width.unwrap_or(0.);
+            v.check(view_width > 0., cam_at("view_width"), String::from("camera.view_width"), "must be given and positive for an orthographic camera")?;
+            Projection::Orthographic {view_width}
+        },
+        ProjectionDesc::Equirectangular => Projection::Equirectangular,
+        ProjectionDesc::Fisheye => {
+            v.check(cam.fov > 0. && cam.fov <= 360., cam_at("fov"), String::from("camera.fov"), "must be between 0 and 360 degrees")?;
+            Projection::Fisheye {fov: cam.fov}
+        }
+    };
+    v.check(cam.aspect_ratio > 0., cam_at("aspect_ratio"), String::from("camera.aspect_ratio"), "must be positive")?;
+    v.check(cam.defocus_angle >= 0., cam_at("defocus_angle"), String::from("camera.defocus_angle"), "must not be negative")?;
+    let focus_distance: f64 = cam.focus_distance.unwrap_or((look_from - look_at).len());
+    v.check(focus_distance > 0., cam_at("focus_distance"), String::from("camera.focus_distance"), "must be positive")?;
+    v.check(cam.shutter[0] <= cam.shutter[1], cam_at("shutter"), String::from("camera.shutter"), "must not close before it opens")?;
+
+    let (render, render_span): (RenderDesc, Range<usize>) = match desc.render {
+        Some(r) => {
+            let span = r.span();
+            (r.into_inner(), span)
+        },
+        None => (RenderDesc::default(), 0..0)
+    };
+    let render_at = |f: &str| field_span(spans.render.as_ref(), f, &render_span);
+    let seed: u64 = seed.or(render.seed).unwrap_or(DEFAULT_SEED);
+    let image_width: u32 = render.width.unwrap_or(400);
+    v.check(image_width > 0, render_at("width"), String::from("render.width"), "must be positive")?;
+    v.check((image_width as f64/cam.aspect_ratio) as u32 > 0, render_at("width"), String::from("render.width"), "too small for the aspect ratio, the image would have no rows")?;
+    let samples: u32 = render.samples.unwrap_or(16);
+    v.check(samples > 0, render_at("samples"), String::from("render.samples"), "must be positive")?;
+    v.check(render.threads != Some(0), render_at("threads"), String::from("render.threads"), "must be positive")?;
+    v.check(render.white > 0., render_at("white"), String::from("render.white"), "must be positive")?;
+    let format: Option<Format> = match &render.format {
+        None => None,
+        Some(name) => Some(Format::from_name(name).ok_or_else(|| SceneError::Invalid {
+            path: path.to_path_buf(), line: v.line(render_at("format").start), field: String::from("render.format"), msg: format!("expected one of {}", FORMATS.join(", "))
+        })?)
+    };
+    let sampler: SamplerKind = match &render.sampler {
+        None => SamplerKind::Sobol,
+        Some(name) => SamplerKind::from_name(name).ok_or_else(|| SceneError::Invalid {
+            path: path.to_path_buf(), line: v.line(render_at("sampler").start), field: String::from("render.sampler"), msg: format!("expected one of {}", SAMPLERS.join(", "))
+        })?
+    };
+    let filter: PixelFilter = match &render.filter {
+        None => PixelFilter::Box,
+        Some(name) => PixelFilter::from_name(name).ok_or_else(|| SceneError::Invalid {
+            path: path.to_path_buf(), line: v.line(render_at("filter").start), field: String::from("render.filter"), msg: format!("expected one of {}", FILTERS.join(", "))
+        })?
+    };
+    v.check(render.filter_radius.is_none_or(|r| r >= MIN_RADIUS), render_at("filter_radius"), String::from("render.filter_radius"), "must be at least 0.5 pixels")?;
+    let adaptive: Option<Adaptive> = if (render.adaptive) {
+        let default: Adaptive = Adaptive::default();
+        let adaptive: Adaptive = Adaptive {min_samples: render.min_samples.unwrap_or(default.min_samples), threshold: render.threshold.unwrap_or(default.threshold)};
+        v.check(adaptive.min_samples > 0 && adaptive.min_samples <= samples, render_at("min_samples"), String::from("render.min_samples"), "must be between 1 and render.samples")?;
+        v.check(adaptive.threshold > 0., render_at("threshold"), String::from("render.threshold"), "must be positive")?;
+        Some(adaptive)
+    } else {
+        v.check(render.min_samples.is_none() && render.threshold.is_none(), render_at("adaptive"), String::from("render.adaptive"), "must be true for min_samples and threshold to apply")?;
+        None
+    };
+    let tone_map: ToneMap = match &render.tonemap {
+        None => ToneMap::Clamp,
+        Some(name) => ToneMap::from_name(name, render.white).ok_or_else(|| SceneError::Invalid {
+            path: path.to_path_buf(), line: v.line(render_at("tonemap").start), field: String::from("render.tonemap"), msg: format!("expected one of {}", TONE_MAPS.join(", "))
+        })?
+    };
+
+    let background: Background = match desc.background {
+        None => Background::Sky,
+        Some(b) => {
+            let span = b.span();
+            match b.into_inner() {
+                BackgroundDesc::Color(c) => Background::Color(vec3(c)),
+                BackgroundDesc::Named(name) => {
+                    v.check(name == "sky", span, String::from("background"), "expected \"sky\" or an [r, g, b] color")?;
+                    Background::Sky
+                }
+            }
+        }
+    };
+
+    let mut materials: HashMap<String, Materials> = HashMap::new();
+    for (name, entry) in desc.materials.into_iter() {
+        let (m, span): (MaterialDesc, Range<usize>) = v.entry(entry, &format!("materials.{}", name))?;
+        let field = |f: &str| format!("materials.{}.{}", name, f);
+        let at = |f: &str| field_span(spans.materials.get(&name), f, &span);
+        let material_seed: u64 = hash(seed, hash_str(&name));                      // Keyed by name, independent of the order materials are built in
+        let mat: Materials = match m {
+            MaterialDesc::Lambertian {color} => Materials::Lambertian(Lambertian {color: v.texture(color, dir, material_seed, at("color"), field("color"))?}),
+            MaterialDesc::Metal {color, fuzz} => {
+                v.check((0. ..=1.).contains(&fuzz), at("fuzz"), field("fuzz"), "must be between 0 and 1")?;
+                Materials::Metal(Metal {color: v.texture(color, dir, material_seed, at("color"), field("color"))?, fuzz})
+            },
+            MaterialDesc::Dielectric {color, refraction_idx} => {
+                v.check(refraction_idx > 0., at("refraction_idx"), field("refraction_idx"), "must be positive")?;
+                Materials::Dielectric(Dielectric {color: v.texture(color, dir, material_seed, at("color"), field("color"))?, refraction_idx})
+            },
+            MaterialDesc::Light {color, intensity} => {
+                v.check(intensity >= 0., at("intensity"), field("intensity"), "must not be negative")?;
+                Materials::DiffuseLight(DiffuseLight {color: v.texture(color, dir, material_seed, at("color"), field("color"))?, intensity})
+            }
+        };
+        materials.insert(name, mat);
+    }
+
+    let mut surfaces: Vec<Surfaces> = vec![];
+    for (i, entry) in desc.surfaces.into_iter().enumerate() {
+        let (s, span): (SurfaceDesc, Range<usize>) = v.entry(entry, &format!("surfaces[{}]", i))?;
+        let field = |f: &str| format!("surfaces[{}].{}", i, f);
+        let at = |f: &str| field_span(spans.surfaces.get(i), f, &span);
+        let lookup = |name: &String| -> Result<Materials, SceneError> {
+            materials.get(name).cloned().ok_or_else(|| SceneError::Invalid {
+                path: path.to_path_buf(), line: v.line(at("material").start), field: field("material"), msg: format!("unknown material '{}'", name)
+            })
+        };
+        match s {
+            SurfaceDesc::Sphere {center, center1, radius, material} => {
+                v.check(radius > 0., at("radius"), field("radius"), "must be positive")?;
+                surfaces.push(Surfaces::Sphere(Sphere {center: vec3(center), center1: center1.map(vec3), radius, mat: lookup(&material)?}));
+            },
+            SurfaceDesc::Triangle {vertices, normals, motion, material} => {
+                let [a, b, c]: [Vec3; 3] = vertices.map(vec3);
+                v.check(!collinear(b - a, c - a), at("vertices"), field("vertices"), "must not be collinear")?;
+                let n: Option<[Vec3; 3]> = normals.map(|n| n.map(vec3));
+                v.check(n.is_none_or(|n| n.iter().all(|n| n.len() > 0. && n.len().is_finite())), at("normals"), field("normals"), "must have finite, nonzero lengths")?;
+                surfaces.push(Surfaces::Triangle(Triangle {v: [a, b, c], n: n.map(|n| n.map(|n| n.unit())), uv: None, motion: motion.map(vec3), mat: lookup(&material)?}));
+            },
+            SurfaceDesc::Quad {corner, u, v: side, motion, material} => {
+                let (q, u, w): (Vec3, Vec3, Vec3) = (vec3(corner), vec3(u), vec3(side));
+                v.check(!collinear(u, w), at("v"), field("v"), "must not be parallel to u")?;
+                let mat: Materials = lookup(&material)?;
+                let motion: Option<Vec3> = motion.map(vec3);
+                surfaces.push(Surfaces::Triangle(Triangle {v: [q, q + u, q + u + w], n: None, uv: Some([(0., 0.), (1., 0.), (1., 1.)]), motion, mat: mat.clone()}));
+                surfaces.push(Surfaces::Triangle(Triangle {v: [q, q + u + w, q + w], n: None, uv: Some([(0., 0.), (1., 1.), (0., 1.)]), motion, mat}));
+            },
+            SurfaceDesc::Mesh {path: mesh_path, material, scale, translate, motion} => {
+                v.check(scale > 0., at("scale"), field("scale"), "must be positive")?;
+                let default_mat: Materials = match material {
+                    Some(name) => lookup(&name)?,
+                    None => Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.8, 0.8, 0.8))})
+                };
+                let mut mesh: Mesh = load_obj(&dir.join(mesh_path), default_mat).map_err(SceneError::Mesh)?;
+                mesh.transform(scale, vec3(translate));
+                for t in mesh.triangles.iter_mut() {
+                    t.motion = motion.map(vec3);
+                }
+                surfaces.extend(mesh.into_surfaces());
+            }
+        }
+    }
+
+    let mut camera: CameraBuilder = CameraBuilder::new()
+        .look_from(look_from)
+        .look_at(look_at)
+        .up(vec3(cam.up))
+        .defocus_angle(cam.defocus_angle)
+        .focus_distance(focus_distance)
+        .aspect_ratio(cam.aspect_ratio)
+        .image_width(image_width)
+        .max_depth(render.max_depth)
+        .projection(projection)
+        .samples(samples)
+        .sampler(sampler)
+        .filter(filter)
+        .seed(seed)
+        .scene_fingerprint(fingerprint(&src))
+        .shutter(cam.shutter[0], cam.shutter[1])
+        .aovs(render.aovs)
+        .tone_map(tone_map)
+        .exposure(render.exposure);
+    if (render.denoise) {
+        camera = camera.denoiser(Denoiser::default());
+    }
+    if let Some(radius) = render.filter_radius {
+        camera = camera.filter_radius(radius);
+    }
+    if let Some(adaptive) = adaptive {
+        camera = camera.adaptive(adaptive);
+    }
+    if (render.heatmap) {
+        camera = camera.heatmap(true);
+    }
+    v.check(render.pass_samples != Some(0), render_at("pass_samples"), String::from("render.pass_samples"), "must be positive")?;
+    if let Some(pass_samples) = render.pass_samples {
+        camera = camera.progressive(pass_samples);
+    }
+    if let Some(checkpoint) = render.checkpoint {
+        camera = camera.checkpoint(checkpoint);
+    }
+    if let Some(threads) = render.threads {
+        camera = camera.threads(threads);
+    }
+    if let Some(format) = format {
+        camera = camera.format(format);
+    }
+    if let Some(output) = render.output {
+        camera = camera.output_file(output);
+    }
+
+    Ok(Scene {
+        world: World::new(surfaces, background),
+        camera
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const BASE: &str = "[camera]\nlook_from = [0, 0, 5]\nlook_at = [0, 0, 0]\n\n[render]\nwidth = 16\nsamples = 1\n\n[materials.grey]\ntype = \"lambertian\"\ncolor = [0.5, 0.5, 0.5]\n";
+
+    fn error(name: &str, src: &str) -> (usize, String, String) {
+        let path: PathBuf = env::temp_dir().join(format!("raytracer-test-{}-{}.toml", std::process::id(), name));
+        fs::write(&path, src).unwrap();
+        let result: Result<Scene, SceneError> = load_scene(&path, None);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(SceneError::Invalid {line, field, msg, ..}) => (line, field, msg),
+            Err(SceneError::Parse {line, msg, ..}) => (line, String::new(), msg),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("loaded an invalid scene")
+        }
+    }
+
+    #[test]
+    fn loads_a_valid_scene() {
+        let path: PathBuf = env::temp_dir().join(format!("raytracer-test-{}-valid.toml", std::process::id()));
+        fs::write(&path, format!("{}\n[[surfaces]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"grey\"\n", BASE)).unwrap();
+        let result: Result<Scene, SceneError> = load_scene(&path, None);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_ok_and(|scene| scene.world.surfaces.len() == 1));
+    }
+
+    #[test]
+    fn bouncing_keeps_the_sphere_layout() {
+        let spheres = |scene: Scene| -> Vec<[f64; 4]> {
+            scene.world.surfaces.iter().map(|s| match s {
+                Surfaces::Sphere(sphere) => [sphere.center.0, sphere.center.1, sphere.center.2, sphere.radius],
+                _ => panic!("expected only spheres")
+            }).collect()
+        };
+        assert_eq!(spheres(random_spheres(false, 5)), spheres(random_spheres(true, 5)));
+    }
+
+    #[test]
+    fn errors_point_at_the_field() {
+        let cases: [(&str, String, usize, &str, &str); 8] = [
+            ("width", BASE.replace("width = 16", "width = 0"), 6, "render.width", "positive"),
+            ("samples", BASE.replace("samples = 1", "samples = 1\nthreads = 0"), 8, "render.threads", "positive"),
+            ("look_at", BASE.replace("look_at = [0, 0, 0]", "look_at = [0, 0, 5]"), 3, "camera.look_at", "differ"),
+            ("fuzz", format!("{}\n[materials.steel]\ntype = \"metal\"\ncolor = [1, 1, 1]\nfuzz = 2\n", BASE), 16, "materials.steel.fuzz", "between 0 and 1"),
+            ("radius", format!("{}\n[[surfaces]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\n\nradius = -1\nmaterial = \"grey\"\n", BASE), 17, "surfaces[0].radius", "positive"),
+            ("material", format!("{}\n[[surfaces]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"gold\"\n", BASE), 17, "surfaces[0].material", "unknown material"),
+            ("normals", format!("{}\n[[surfaces]]\ntype = \"triangle\"\nvertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]\nnormals = [[0, 0, 1], [0, 0, 0], [0, 0, 1]]\nmaterial = \"grey\"\n", BASE), 16, "surfaces[0].normals", "nonzero"),
+            ("collinear", format!("{}\n[[surfaces]]\ntype = \"triangle\"\nvertices = [[0, 0, 0], [1, 1, 1], [2, 2, 2]]\nmaterial = \"grey\"\n", BASE), 15, "surfaces[0].vertices", "collinear")
+        ];
+        for (name, src, line, field, msg) in cases {
+            let (got_line, got_field, got_msg): (usize, String, String) = error(name, &src);
+            assert!(got_line == line && got_field == field && got_msg.contains(msg), "{}: got {}: {}: {}", name, got_line, got_field, got_msg);
+        }
+    }
+
+    #[test]
+    fn parse_errors_have_a_line() {
+        let (line, _, msg): (usize, String, String) = error("parse", &BASE.replace("samples = 1", "samples = \"many\""));
+        assert!(line == 7, "got line {}: {}", line, msg);
+    }
+}
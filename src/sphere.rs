@@ -1,18 +1,38 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
-use crate::surface::{HitRecord, Hittable};
+use crate::surface::{HitRecord, Hittable, Sample};
 use crate::interval::Interval;
+use crate::aabb::Aabb;
 use crate::materials::Materials;
+use crate::onb::Onb;
+use std::f64::consts::PI;
 
 pub struct Sphere {
-    pub center: Vec3,
+    pub center: Vec3,          // Center at time 0
+    pub center1: Option<Vec3>, // Center at time 1 for a moving sphere, static if None
     pub radius: f64,
     pub mat: Materials
 }
 
+impl Sphere {
+    pub fn center_at(&self, time: f64) -> Vec3 { // Linear motion between the two keyed centers, held still outside [0, 1]
+        match self.center1 {
+            Some(c1) => self.center + time.clamp(0., 1.)*(c1 - self.center),
+            None => self.center
+        }
+    }
+
+    fn uv(p: Vec3) -> (f64, f64) { // Spherical mapping of a point on the unit sphere: u wraps around the y axis starting at -x, v goes from the bottom pole to the top one
+        let theta: f64 = (-p.1).clamp(-1., 1.).acos();
+        let phi: f64 = (-p.2).atan2(p.0) + PI;
+        (phi/(2.*PI), theta/PI)
+    }
+}
+
 impl Hittable for Sphere {
-    fn hit(&self, r: Ray, t_range: Interval, rec: &mut HitRecord) -> bool {
-        let oc: Vec3 = r.ori - self.center;
+    fn hit<'a>(&'a self, r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool {
+        let center: Vec3 = self.center_at(r.time);
+        let oc: Vec3 = r.ori - center;
         let a: f64 = r.dir.l2norm();
         let half_b: f64 = oc*r.dir;
         let c: f64 = oc.l2norm() - self.radius*self.radius;
@@ -32,9 +52,47 @@ impl Hittable for Sphere {
 
         rec.t = root;
         rec.p = r.at(rec.t);
-        let outward_normal: Vec3 = (rec.p - self.center)/self.radius;
+        let outward_normal: Vec3 = (rec.p - center)/self.radius;
         rec.set_face_normal(r, outward_normal);
-        rec.mat = self.mat;
+        (rec.u, rec.v) = Self::uv(outward_normal);
+        rec.mat = &self.mat;
         true
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Aabb {
+        let r: Vec3 = Vec3(self.radius, self.radius, self.radius);
+        let start: Aabb = Aabb::from_points(self.center - r, self.center + r);
+        match self.center1 {
+            Some(c1) => Aabb::merge(start, Aabb::from_points(c1 - r, c1 + r)),           // Covers the whole motion
+            None => start
+        }
+    }
+}
+
+impl Sample for Sphere {
+    fn sample(&self, origin: Vec3, time: f64, u: (f64, f64)) -> Vec3 {                            // Uniform over the cone of directions subtended by the sphere
+        let to_center: Vec3 = self.center_at(time) - origin;
+        let d2: f64 = to_center.l2norm();
+        if (d2 <= self.radius*self.radius) {
+            return Vec3::uniform_sphere(u);
+        }
+        let cos_theta_max: f64 = (1. - self.radius*self.radius/d2).sqrt();
+        let phi: f64 = 2.*PI*u.0;
+        let z: f64 = 1. + u.1*(cos_theta_max - 1.);
+        let sin_theta: f64 = (1. - z*z).max(0.).sqrt();
+        Onb::new(to_center).local(Vec3(phi.cos()*sin_theta, phi.sin()*sin_theta, z))
+    }
+
+    fn pdf(&self, origin: Vec3, dir: Vec3, time: f64) -> f64 {
+        let mut rec: HitRecord = HitRecord::new_empty();
+        if (!self.hit(Ray {ori: origin, dir, time}, Interval{min: 0.000001, max: f64::INFINITY}, &mut rec)) {
+            return 0.;
+        }
+        let d2: f64 = (self.center_at(time) - origin).l2norm();
+        if (d2 <= self.radius*self.radius) {
+            return 1./(4.*PI);
+        }
+        let cos_theta_max: f64 = (1. - self.radius*self.radius/d2).sqrt();
+        1./(2.*PI*(1. - cos_theta_max))
+    }
+}
@@ -1,31 +1,45 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::interval::Interval;
+use crate::aabb::Aabb;
 use crate::materials::{Materials, Lambertian};
+use crate::texture::Textures;
 
 pub trait Hittable {
-    fn hit(&self, r: Ray, t_range: Interval, rec: &mut HitRecord) -> bool;
+    fn hit<'a>(&'a self, r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool;
+    fn bounding_box(&self) -> Aabb;
+}
+
+pub trait Sample { // Surfaces that can be importance sampled as light sources
+    fn sample(&self, origin: Vec3, time: f64, u: (f64, f64)) -> Vec3; // Direction from origin towards the surface as placed at time, picked by the sample values u
+    fn pdf(&self, origin: Vec3, dir: Vec3, time: f64) -> f64;          // Solid angle density of sample returning dir
 }
 
 #[derive(Clone, Copy)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub p: Vec3,
     pub n: Vec3,
     pub t: f64,
+    pub u: f64,         // Surface coordinates of the hit point
+    pub v: f64,
     pub front: bool,
-    pub mat: Materials
+    pub mat: &'a Materials,
+    pub id: usize       // Index of the hit surface within the world
 }
 
-impl HitRecord {
-    pub fn new_empty() -> HitRecord {
+static NO_MATERIAL: Materials = Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0., 0., 0.))}); // Placeholder until something is hit
+
+impl HitRecord<'_> {
+    pub fn new_empty() -> HitRecord<'static> {
         HitRecord {
             p: Vec3(0., 0., 0.),
             n: Vec3(0., 0., 0.),
             t: f64::INFINITY,
+            u: 0.,
+            v: 0.,
             front: false,
-            mat: Materials::Lambertian(Lambertian {
-                color: Vec3(0., 0., 0.)
-            })
+            mat: &NO_MATERIAL,
+            id: 0
         }
     }
 
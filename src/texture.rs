@@ -0,0 +1,229 @@
+use std::sync::Arc;
+use std::path::Path;
+use image::{ColorType, DynamicImage, ImageError, Rgb32FImage};
+
+use crate::vec3::Vec3;
+use crate::color::srgb_to_linear;
+use crate::noise::Perlin;
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3; // Color at surface coordinates (u, v) and hit point p
+}
+
+#[derive(Clone)]
+pub enum Textures {
+    Solid(Vec3),
+    Checker(Checker),
+    UvChecker(UvChecker),
+    Image(ImageTexture),
+    Noise(Noise),
+    Marble(Marble),
+    Wood(Wood)
+}
+
+#[derive(Clone, Copy)]
+pub struct Checker { // Alternating cubes in world space, independent of how the surface is parameterised
+    pub scale: f64,  // Side of each cube
+    pub even: Vec3,
+    pub odd: Vec3
+}
+
+#[derive(Clone, Copy)]
+pub struct UvChecker { // Alternating squares in (u, v) space, follows the surface
+    pub scale: f64,    // Squares along each of u and v
+    pub even: Vec3,
+    pub odd: Vec3
+}
+
+#[derive(Clone)]
+pub struct Noise { // fBm blended between two colors
+    pub perlin: Arc<Perlin>,
+    pub scale: f64,   // Spatial frequency
+    pub octaves: u32,
+    pub low: Vec3,
+    pub high: Vec3
+}
+
+#[derive(Clone)]
+pub struct Marble { // Sine bands along z distorted by turbulence
+    pub perlin: Arc<Perlin>,
+    pub scale: f64,
+    pub octaves: u32,
+    pub low: Vec3,
+    pub high: Vec3
+}
+
+#[derive(Clone)]
+pub struct Wood { // Concentric rings around the y axis, wobbled by noise
+    pub perlin: Arc<Perlin>,
+    pub scale: f64,   // Rings per unit of distance from the axis
+    pub octaves: u32,
+    pub low: Vec3,    // Color between rings
+    pub high: Vec3    // Color of the rings
+}
+
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear // Weighted average of the four closest texels
+}
+
+#[derive(Clone, Copy)]
+pub enum Wrap { // How coordinates outside [0, 1] are brought back into the image
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+#[derive(Clone)]
+pub struct ImageTexture {
+    w: u32,
+    h: u32,
+    pixels: Arc<Vec<[f32; 3]>>, // Linear colors, row-major, top row first; shared by every material using the image
+    pub filter: Filter,
+    pub wrap: Wrap
+}
+
+impl ImageTexture {
+    pub fn load(path: &Path, srgb: bool) -> Result<ImageTexture, ImageError> { // srgb decodes 8/16-bit color images, leave it off for data like normal or roughness maps
+        let img: DynamicImage = image::open(path)?;
+        let float_source: bool = matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F); // Already linear, e.g. Radiance HDR or OpenEXR
+        let rgb: Rgb32FImage = img.into_rgb32f();
+        let decode = |c: f32| -> f32 {
+            if (srgb && !float_source) {srgb_to_linear(c as f64) as f32} else {c}
+        };
+        let pixels: Vec<[f32; 3]> = rgb.pixels().map(|px| [decode(px[0]), decode(px[1]), decode(px[2])]).collect();
+        Ok(ImageTexture {w: rgb.width(), h: rgb.height(), pixels: Arc::new(pixels), filter: Filter::Bilinear, wrap: Wrap::Repeat})
+    }
+
+    fn texel(&self, i: i64, j: i64) -> Vec3 {
+        let x: i64 = Self::wrap_index(i, self.w as i64, self.wrap);
+        let y: i64 = Self::wrap_index(j, self.h as i64, self.wrap);
+        let px: [f32; 3] = self.pixels[(y*self.w as i64 + x) as usize];
+        Vec3(px[0] as f64, px[1] as f64, px[2] as f64)
+    }
+
+    fn wrap_index(i: i64, n: i64, wrap: Wrap) -> i64 {
+        match wrap {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {                                                       // Every other copy of the image is flipped
+                let k: i64 = i.rem_euclid(2*n);
+                if (k >= n) {2*n - 1 - k} else {k}
+            }
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let cell: i64 = (p.0/self.scale).floor() as i64 + (p.1/self.scale).floor() as i64 + (p.2/self.scale).floor() as i64;
+        if (cell.rem_euclid(2) == 0) {self.even} else {self.odd}
+    }
+}
+
+impl Texture for UvChecker {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let cell: i64 = (u*self.scale).floor() as i64 + (v*self.scale).floor() as i64;
+        if (cell.rem_euclid(2) == 0) {self.even} else {self.odd}
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 { // v = 0 at the bottom of the image
+        let x: f64 = u*self.w as f64;
+        let y: f64 = (1. - v)*self.h as f64;
+        match self.filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {                                                   // Texel centers sit at half-integer coordinates
+                let (x, y): (f64, f64) = (x - 0.5, y - 0.5);
+                let (i, j): (i64, i64) = (x.floor() as i64, y.floor() as i64);
+                let (fx, fy): (f64, f64) = (x - x.floor(), y - y.floor());
+                let top: Vec3 = (1. - fx)*self.texel(i, j) + fx*self.texel(i + 1, j);
+                let bottom: Vec3 = (1. - fx)*self.texel(i, j + 1) + fx*self.texel(i + 1, j + 1);
+                (1. - fy)*top + fy*bottom
+            }
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let t: f64 = (0.5*(1. + self.perlin.fbm(self.scale*p, self.octaves))).clamp(0., 1.);
+        (1. - t)*self.low + t*self.high
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let t: f64 = 0.5*(1. + (self.scale*p.2 + 10.*self.perlin.turbulence(p, self.octaves)).sin());
+        (1. - t)*self.low + t*self.high
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        let r: f64 = (p.0*p.0 + p.2*p.2).sqrt()*self.scale + 2.*self.perlin.fbm(p, self.octaves);
+        let t: f64 = (r - r.floor()).powi(3);                                       // Thin dark rings over wide light bands
+        (1. - t)*self.low + t*self.high
+    }
+}
+
+impl Texture for Textures {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        match self {
+            Textures::Solid(color) => *color,
+            Textures::Checker(c) => c.value(u, v, p),
+            Textures::UvChecker(c) => c.value(u, v, p),
+            Textures::Image(img) => img.value(u, v, p),
+            Textures::Noise(n) => n.value(u, v, p),
+            Textures::Marble(m) => m.value(u, v, p),
+            Textures::Wood(w) => w.value(u, v, p)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(filter: Filter, wrap: Wrap) -> ImageTexture { // One row of four texels, the red channel holds the column
+        let pixels: Vec<[f32; 3]> = (0..4).map(|i| [i as f32, 0., 0.]).collect();
+        ImageTexture {w: 4, h: 1, pixels: Arc::new(pixels), filter, wrap}
+    }
+
+    #[test]
+    fn wrap_indices() {
+        let cases: [(Wrap, [i64; 10]); 3] = [                                   // Columns -5..5 of a four texel row
+            (Wrap::Repeat, [3, 0, 1, 2, 3, 0, 1, 2, 3, 0]),
+            (Wrap::Clamp, [0, 0, 0, 0, 0, 0, 1, 2, 3, 3]),
+            (Wrap::Mirror, [3, 3, 2, 1, 0, 0, 1, 2, 3, 3])
+        ];
+        for (wrap, expected) in cases {
+            let got: Vec<i64> = (-5..5).map(|i| ImageTexture::wrap_index(i, 4, wrap)).collect();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn nearest_lookups_outside_the_image() {
+        let cases: [(Wrap, [f64; 4]); 3] = [                                    // At u = -0.375, 0.125, 1.125, 1.875
+            (Wrap::Repeat, [2., 0., 0., 3.]),
+            (Wrap::Clamp, [0., 0., 3., 3.]),
+            (Wrap::Mirror, [1., 0., 3., 0.])
+        ];
+        for (wrap, expected) in cases {
+            let tex: ImageTexture = ramp(Filter::Nearest, wrap);
+            let got: Vec<f64> = [-0.375, 0.125, 1.125, 1.875].iter().map(|&u| tex.value(u, 0.5, Vec3(0., 0., 0.)).0).collect();
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn bilinear_blends_across_the_seam() {
+        let at_edge = |wrap: Wrap| ramp(Filter::Bilinear, wrap).value(1., 0.5, Vec3(0., 0., 0.)).0; // Halfway between the last texel and whatever follows it
+        assert_eq!(at_edge(Wrap::Repeat), 1.5);
+        assert_eq!(at_edge(Wrap::Clamp), 3.);
+        assert_eq!(at_edge(Wrap::Mirror), 3.);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy)]
+pub enum ToneMap { // Maps scene radiance to display values in [0, 1], before the sRGB encoding
+    Clamp,                          // Cuts everything above 1
+    Reinhard,                       // L/(1 + L) on luminance, never reaches white
+    ExtendedReinhard {white: f64},  // Reinhard reaching white at luminance white
+    Hable,                          // Uncharted 2 filmic curve
+    Aces                            // Hill's fit of the ACES reference and output transforms
+}
+
+pub const NAMES: [&str; 5] = ["clamp", "reinhard", "reinhard-extended", "hable", "aces"];
+
+impl ToneMap {
+    pub fn from_name(name: &str, white: f64) -> Option<ToneMap> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "reinhard-extended" => Some(ToneMap::ExtendedReinhard {white}),
+            "hable" => Some(ToneMap::Hable),
+            "aces" => Some(ToneMap::Aces),
+            _ => None
+        }
+    }
+
+    pub fn apply(self, c: Vec3, exposure: f64) -> Vec3 { // exposure in stops, 0 leaves the radiance as is
+        let c: Vec3 = 2f64.powf(exposure)*c;
+        let mapped: Vec3 = match self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l/(1. + l)),
+            ToneMap::ExtendedReinhard {white} => scale_luminance(c, |l| l*(1. + l/(white*white))/(1. + l)),
+            ToneMap::Hable => {
+                let white_scale: f64 = 1./hable(11.2);                              // Linear white point of the curve
+                white_scale*Vec3(hable(2.*c.0), hable(2.*c.1), hable(2.*c.2))
+            },
+            ToneMap::Aces => {
+                let v: Vec3 = mat_mul(ACES_INPUT, c);
+                mat_mul(ACES_OUTPUT, Vec3(rrt_odt_fit(v.0), rrt_odt_fit(v.1), rrt_odt_fit(v.2)))
+            }
+        };
+        Vec3(mapped.0.clamp(0., 1.), mapped.1.clamp(0., 1.), mapped.2.clamp(0., 1.))
+    }
+}
+
+fn luminance(c: Vec3) -> f64 { // Rec. 709 weights
+    0.2126*c.0 + 0.7152*c.1 + 0.0722*c.2
+}
+
+fn scale_luminance(c: Vec3, curve: impl Fn(f64) -> f64) -> Vec3 { // Applies curve to luminance and scales the color along, keeping its hue
+    let l: f64 = luminance(c);
+    if (l <= 0.) {
+        return Vec3(0., 0., 0.);
+    }
+    (curve(l)/l)*c
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f): (f64, f64, f64, f64, f64, f64) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x*(a*x + c*b) + d*e)/(x*(a*x + b) + d*f) - e/f
+}
+
+const ACES_INPUT: [[f64; 3]; 3] = [ // sRGB to the ACES fit's working space, including the RRT saturation
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777]
+];
+
+const ACES_OUTPUT: [[f64; 3]; 3] = [ // Back to sRGB after the ODT
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602]
+];
+
+fn rrt_odt_fit(v: f64) -> f64 {
+    (v*(v + 0.0245786) - 0.000090537)/(v*(0.983729*v + 0.4329510) + 0.238081)
+}
+
+fn mat_mul(m: [[f64; 3]; 3], v: Vec3) -> Vec3 {
+    Vec3(
+        m[0][0]*v.0 + m[0][1]*v.1 + m[0][2]*v.2,
+        m[1][0]*v.0 + m[1][1]*v.1 + m[1][2]*v.2,
+        m[2][0]*v.0 + m[2][1]*v.1 + m[2][2]*v.2
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_into_unit_range() {
+        for name in NAMES {
+            let tone_map: ToneMap = ToneMap::from_name(name, 4.).unwrap();
+            let black: Vec3 = tone_map.apply(Vec3(0., 0., 0.), 0.);
+            assert!(black.0.abs() < 1e-3 && black.1.abs() < 1e-3 && black.2.abs() < 1e-3, "{} lifts black", name);
+            let mut last: f64 = 0.;
+            for l in [0.01, 0.1, 0.5, 1., 2., 10., 1000.] {
+                let c: Vec3 = tone_map.apply(Vec3(l, l, l), 0.);
+                assert!([c.0, c.1, c.2].iter().all(|x| (0. ..=1.).contains(x)), "{} leaves [0, 1] at {}", name, l);
+                assert!(c.1 >= last, "{} isn't monotonic at {}", name, l);
+                last = c.1;
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::surface::{HitRecord, Hittable, Sample};
+use crate::interval::Interval;
+use crate::aabb::Aabb;
+use crate::materials::Materials;
+
+pub struct Triangle {
+    pub v: [Vec3; 3],         // Vertex positions, counter-clockwise when seen from the front
+    pub n: Option<[Vec3; 3]>, // Per-vertex normals for smooth shading, flat shading if None
+    pub uv: Option<[(f64, f64); 3]>, // Per-vertex texture coordinates, barycentric coordinates are reported if None
+    pub motion: Option<Vec3>, // Translation reached at time 1 for a moving triangle, static if None
+    pub mat: Materials
+}
+
+impl Triangle {
+    fn geometric_normal(&self) -> Vec3 {
+        (self.v[1] - self.v[0]).x(self.v[2] - self.v[0])
+    }
+
+    fn offset_at(&self, time: f64) -> Vec3 { // Linear motion from the keyed vertices, held still outside [0, 1]
+        match self.motion {
+            Some(m) => time.clamp(0., 1.)*m,
+            None => Vec3(0., 0., 0.)
+        }
+    }
+
+    fn area(&self) -> f64 {
+        0.5*self.geometric_normal().len()
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit<'a>(&'a self, r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool {       // Woop, Benthin and Wald 2013: edges shared by two triangles never let a ray slip through
+        let kz: usize = r.dir.abs().max_axis();                                     // Permute axes so the ray travels mostly along z
+        let mut kx: usize = (kz + 1) % 3;
+        let mut ky: usize = (kx + 1) % 3;
+        if (r.dir.axis(kz) < 0.) {
+            std::mem::swap(&mut kx, &mut ky);                                       // Preserves the winding order
+        }
+        let sx: f64 = r.dir.axis(kx)/r.dir.axis(kz);                               // Shear that maps the ray onto the +z axis
+        let sy: f64 = r.dir.axis(ky)/r.dir.axis(kz);
+        let sz: f64 = 1./r.dir.axis(kz);
+
+        let ori: Vec3 = r.ori - self.offset_at(r.time);                             // Moving the ray back is the same as moving the triangle forward
+        let a: Vec3 = self.v[0] - ori;
+        let b: Vec3 = self.v[1] - ori;
+        let c: Vec3 = self.v[2] - ori;
+        let ax: f64 = a.axis(kx) - sx*a.axis(kz);
+        let ay: f64 = a.axis(ky) - sy*a.axis(kz);
+        let bx: f64 = b.axis(kx) - sx*b.axis(kz);
+        let by: f64 = b.axis(ky) - sy*b.axis(kz);
+        let cx: f64 = c.axis(kx) - sx*c.axis(kz);
+        let cy: f64 = c.axis(ky) - sy*c.axis(kz);
+
+        let e0: f64 = cx*by - cy*bx;                                                // Scaled barycentric coordinates as 2D edge functions
+        let e1: f64 = ax*cy - ay*cx;
+        let e2: f64 = bx*ay - by*ax;
+        if ((e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.)) {
+            return false;
+        }
+        let det: f64 = e0 + e1 + e2;
+        if (det == 0.) {
+            return false;
+        }
+
+        let t_scaled: f64 = e0*sz*a.axis(kz) + e1*sz*b.axis(kz) + e2*sz*c.axis(kz);
+        let t: f64 = t_scaled/det;
+        if (!t_range.surrounds(t)) {
+            return false;
+        }
+
+        let (b0, b1, b2): (f64, f64, f64) = (e0/det, e1/det, e2/det);
+        rec.t = t;
+        rec.p = b0*self.v[0] + b1*self.v[1] + b2*self.v[2] + self.offset_at(r.time);
+        rec.set_face_normal(r, self.geometric_normal().unit());
+        if let Some(n) = self.n {                                                   // Interpolated normal, flipped to the same side as the geometric one
+            let shading_n: Vec3 = (b0*n[0] + b1*n[1] + b2*n[2]).unit();
+            rec.n = (if (rec.front) {1.} else {-1.})*shading_n;
+        }
+        (rec.u, rec.v) = match self.uv {
+            Some(uv) => (b0*uv[0].0 + b1*uv[1].0 + b2*uv[2].0, b0*uv[0].1 + b1*uv[1].1 + b2*uv[2].1),
+            None => (b1, b2)
+        };
+        rec.mat = &self.mat;
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let start: Aabb = Aabb::merge(Aabb::from_points(self.v[0], self.v[1]), Aabb::from_points(self.v[2], self.v[2]));
+        match self.motion {
+            Some(m) => Aabb::merge(start, Aabb::merge(Aabb::from_points(self.v[0] + m, self.v[1] + m), Aabb::from_points(self.v[2] + m, self.v[2] + m))),
+            None => start
+        }
+    }
+}
+
+impl Sample for Triangle {
+    fn sample(&self, origin: Vec3, time: f64, u: (f64, f64)) -> Vec3 {                            // Uniform over the triangle's area
+        let sqrt_r1: f64 = u.0.sqrt();
+        let r2: f64 = u.1;
+        let p: Vec3 = (1. - sqrt_r1)*self.v[0] + (sqrt_r1*(1. - r2))*self.v[1] + (sqrt_r1*r2)*self.v[2];
+        p + self.offset_at(time) - origin
+    }
+
+    fn pdf(&self, origin: Vec3, dir: Vec3, time: f64) -> f64 {                     // Area density converted to solid angle
+        let mut rec: HitRecord = HitRecord::new_empty();
+        if (!self.hit(Ray {ori: origin, dir, time}, Interval{min: 0.000001, max: f64::INFINITY}, &mut rec)) {
+            return 0.;
+        }
+        let dist2: f64 = rec.t*rec.t*dir.l2norm();
+        let cos: f64 = (dir.unit()*self.geometric_normal().unit()).abs();
+        if (cos <= 0.) {
+            return 0.;
+        }
+        dist2/(cos*self.area())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::texture::Textures;
+
+    fn triangle(v: [Vec3; 3]) -> Triangle {
+        Triangle {v, n: None, uv: None, motion: None, mat: Materials::Lambertian(Lambertian {color: Textures::Solid(Vec3(0.5, 0.5, 0.5))})}
+    }
+
+    #[test]
+    fn shared_edge_is_watertight() {
+        let (a, b, c, d): (Vec3, Vec3, Vec3, Vec3) = (Vec3(-1.3, -0.7, 0.1), Vec3(1.1, -0.9, -0.3), Vec3(0.9, 1.7, 0.2), Vec3(-1.1, 1.3, -0.1));
+        let quad: [Triangle; 2] = [triangle([a, b, c]), triangle([a, c, d])]; // Split along the diagonal from a to c
+        let origin: Vec3 = Vec3(0.13, 0.07, 5.);
+        for k in 1..1000 {                                                          // The ends are corners of the quad, on its outline
+            let p: Vec3 = a + (k as f64/1000.)*(c - a);
+            for ori in [origin, Vec3(-3.1, 2.3, -4.7)] {
+                let r: Ray = Ray {ori, dir: p - ori, time: 0.};
+                let mut rec: HitRecord = HitRecord::new_empty();
+                let hits: usize = quad.iter().filter(|t| t.hit(r, Interval {min: 1e-9, max: f64::INFINITY}, &mut rec)).count();
+                assert!(hits > 0, "ray through {:?} slipped between the triangles", p);
+            }
+        }
+    }
+}
@@ -43,6 +43,39 @@ impl Vec3 {
         (if (v*n > 0.) {1.} else {-1.})*v
     }
 
+    pub fn random_cosine_direction() -> Vec3 { // Random unit vector around +z with density proportional to its z coordinate
+        let phi: f64 = 2.*std::f64::consts::PI*random();
+        let r2: f64 = random();
+        let r: f64 = r2.sqrt();
+        Vec3(phi.cos()*r, phi.sin()*r, (1. - r2).sqrt())
+    }
+
+    pub fn uniform_sphere(u: (f64, f64)) -> Vec3 { // Unit vector mapped from a point of the unit square, preserving uniformity
+        let z: f64 = 1. - 2.*u.0;
+        let r: f64 = (1. - z*z).max(0.).sqrt();
+        let phi: f64 = 2.*std::f64::consts::PI*u.1;
+        Vec3(phi.cos()*r, phi.sin()*r, z)
+    }
+
+    pub fn cosine_direction(u: (f64, f64)) -> Vec3 { // Same density as random_cosine_direction, from a point of the unit square
+        let phi: f64 = 2.*std::f64::consts::PI*u.0;
+        let r: f64 = u.1.sqrt();
+        Vec3(phi.cos()*r, phi.sin()*r, (1. - u.1).sqrt())
+    }
+
+    pub fn concentric_disk(u: (f64, f64)) -> Vec3 { // Point of the unit disk from a point of the unit square, with low distortion so stratification carries over (Shirley and Chiu 1997)
+        let (a, b): (f64, f64) = (2.*u.0 - 1., 2.*u.1 - 1.);
+        if (a == 0. && b == 0.) {
+            return Vec3(0., 0., 0.);
+        }
+        let (r, theta): (f64, f64) = if (a.abs() > b.abs()) {
+            (a, std::f64::consts::FRAC_PI_4*(b/a))
+        } else {
+            (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4*(a/b))
+        };
+        Vec3(r*theta.cos(), r*theta.sin(), 0.)
+    }
+
     pub fn reflect(self, n: Vec3) -> Vec3 { // Reflect self based on surface normal
         self - (2.*self*n)*n
     }
@@ -82,6 +115,32 @@ impl Vec3 {
         self/self.len()
     }
 
+    pub fn axis(self, n: usize) -> f64 { // Coordinate by index, 0 to 2
+        match n {
+            0 => self.0,
+            1 => self.1,
+            _ => self.2
+        }
+    }
+
+    pub fn max_axis(self) -> usize { // Index of the largest coordinate
+        if (self.0 >= self.1 && self.0 >= self.2) {
+            0
+        } else if (self.1 >= self.2) {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn abs(self) -> Vec3 { // Coordinate-wise absolute value
+        Vec3(self.0.abs(), self.1.abs(), self.2.abs())
+    }
+
+    pub fn max_coord(self) -> f64 { // Largest of the three coordinates
+        self.0.max(self.1).max(self.2)
+    }
+
     pub fn near_zero(self) -> bool {
         let atol: f64 = 1e-8;
         (self.0.abs() < atol) && (self.1.abs() < atol) && (self.2.abs() < atol)
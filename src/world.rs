@@ -1,37 +1,101 @@
 use crate::vec3::Vec3;
 use crate::ray::Ray;
-use crate::surface::{HitRecord, Hittable};
+use crate::surface::{HitRecord, Hittable, Sample};
 use crate::sphere::Sphere;
+use crate::triangle::Triangle;
 use crate::interval::Interval;
+use crate::materials::Materials;
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
 
 pub enum Surfaces {
-    Sphere(Sphere)
+    Sphere(Sphere),
+    Triangle(Triangle)
 }
 
 impl Hittable for Surfaces {
-    fn hit(&self, r: Ray, t_range: Interval, rec: &mut HitRecord) -> bool {
+    fn hit<'a>(&'a self, r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool {
         match self {
-            Surfaces::Sphere(sphere) => sphere.hit(r, t_range, rec)
+            Surfaces::Sphere(sphere) => sphere.hit(r, t_range, rec),
+            Surfaces::Triangle(triangle) => triangle.hit(r, t_range, rec)
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Surfaces::Sphere(sphere) => sphere.bounding_box(),
+            Surfaces::Triangle(triangle) => triangle.bounding_box()
+        }
+    }
+}
+
+impl Sample for Surfaces {
+    fn sample(&self, origin: Vec3, time: f64, u: (f64, f64)) -> Vec3 {
+        match self {
+            Surfaces::Sphere(sphere) => sphere.sample(origin, time, u),
+            Surfaces::Triangle(triangle) => triangle.sample(origin, time, u)
+        }
+    }
+
+    fn pdf(&self, origin: Vec3, dir: Vec3, time: f64) -> f64 {
+        match self {
+            Surfaces::Sphere(sphere) => sphere.pdf(origin, dir, time),
+            Surfaces::Triangle(triangle) => triangle.pdf(origin, dir, time)
+        }
+    }
+}
+
+impl Surfaces {
+    pub fn mat(&self) -> &Materials {
+        match self {
+            Surfaces::Sphere(sphere) => &sphere.mat,
+            Surfaces::Triangle(triangle) => &triangle.mat
+        }
+    }
+}
+
+pub enum Background { // Radiance seen by rays escaping the scene
+    Sky,                 // White to light blue vertical gradient
+    Color(Vec3)          // Constant color, black for enclosed scenes lit only by emitters
+}
+
+impl Background {
+    pub fn color(&self, r: Ray) -> Vec3 {
+        match self {
+            Background::Sky => {
+                let unit_dir: Vec3 = r.dir.unit();
+                let a: f64 = 0.5*(unit_dir.1 + 1.0);
+                (1.0 - a)*Vec3(1.0, 1.0, 1.0) + a*Vec3(0.5, 0.7, 1.0)
+            },
+            Background::Color(c) => *c
         }
     }
 }
 
 pub struct World {
-    pub surfaces: Vec<Surfaces>
+    pub surfaces: Vec<Surfaces>,
+    pub background: Background,
+    pub lights: Vec<usize>,     // Indices of the emissive surfaces
+    bvh: Bvh
+}
+
+impl World {
+    pub fn new(surfaces: Vec<Surfaces>, background: Background) -> World {
+        let lights: Vec<usize> = (0..surfaces.len())
+            .filter(|&i| matches!(surfaces[i].mat(), Materials::DiffuseLight(_)))
+            .collect();
+        let boxes: Vec<Aabb> = surfaces.iter().map(|s| s.bounding_box()).collect();
+        let bvh: Bvh = Bvh::new(&boxes);
+        World {surfaces, background, lights, bvh}
+    }
 }
 
 impl Hittable for World {
-    fn hit(&self, r: Ray, t_range: Interval, rec: &mut HitRecord) -> bool {
-        let mut temp_rec: HitRecord = HitRecord::new_empty();
-        let mut world_hit: bool = false;
-        let mut nearest: f64 = t_range.max;
-        for surface in self.surfaces.iter() {
-            if (surface.hit(r, Interval{min: t_range.min, max: nearest}, &mut temp_rec)) {
-                nearest = temp_rec.t;
-                world_hit = true;
-                *rec = temp_rec;
-            }
-        }
-        world_hit
+    fn hit<'a>(&'a self, r: Ray, t_range: Interval, rec: &mut HitRecord<'a>) -> bool {
+        self.bvh.hit(&self.surfaces, r, t_range, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
     }
 }
\ No newline at end of file